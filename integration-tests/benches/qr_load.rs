@@ -0,0 +1,96 @@
+//! Redirect latency with and without concurrent QR code renders, on a two-worker runtime.
+//!
+//! Run with `cargo bench -p integration-tests --bench qr_load`.
+
+use integration_tests::Stack;
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Redirects timed per scenario
+const REDIRECTS: usize = 2_000;
+
+/// Clients requesting QR codes in a loop during the load scenario
+const QR_CLIENTS: usize = 32;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(run());
+}
+
+async fn run() {
+    let stack = Arc::new(Stack::new().await);
+    let body = json!({"slug": "bench", "url": "https://example.com/"});
+    assert!(stack.shorten(body).await.status().is_success());
+
+    // Baseline
+    let idle = time_redirects(&stack).await;
+    report("idle", &idle);
+
+    // Under load, every QR request misses the cache with a distinct size
+    let stop = Arc::new(AtomicBool::new(false));
+    let clients: Vec<_> = (0..QR_CLIENTS)
+        .map(|client| {
+            let stack = stack.clone();
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let (mut rendered, mut rejected) = (0, 0);
+                let mut size = 256 + client as u32;
+                while !stop.load(Ordering::Relaxed) {
+                    let response = stack
+                        .visit(&format!(
+                            "/bench/qr?format=png&size={size}&margin={}",
+                            size % 16
+                        ))
+                        .await;
+                    if response.status().is_success() {
+                        rendered += 1;
+                    } else {
+                        rejected += 1;
+                    }
+                    size = 256 + (size + QR_CLIENTS as u32) % 256;
+                }
+                (rendered, rejected)
+            })
+        })
+        .collect();
+    let loaded = time_redirects(&stack).await;
+    stop.store(true, Ordering::Relaxed);
+    let (mut rendered, mut rejected) = (0, 0);
+    for client in clients {
+        let (client_rendered, client_rejected) = client.await.unwrap();
+        rendered += client_rendered;
+        rejected += client_rejected;
+    }
+    report("qr load", &loaded);
+    println!("qr codes: {rendered} rendered or cached, {rejected} rejected with 503");
+}
+
+/// Time sequential redirects, sorted
+async fn time_redirects(stack: &Stack) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(REDIRECTS);
+    for _ in 0..REDIRECTS {
+        let start = Instant::now();
+        let response = stack.visit("/bench").await;
+        latencies.push(start.elapsed());
+        assert!(response.status().is_redirection());
+    }
+    latencies.sort();
+    latencies
+}
+
+/// Print latency percentiles
+fn report(scenario: &str, latencies: &[Duration]) {
+    let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+    println!(
+        "redirects, {scenario}: p50 {:?}, p99 {:?}, max {:?}",
+        percentile(50),
+        percentile(99),
+        latencies[latencies.len() - 1]
+    );
+}
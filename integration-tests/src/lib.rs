@@ -0,0 +1,257 @@
+//! In-process harness for the min-url-rs services, wired to shared in-memory backends.
+
+use axum::{
+    Router,
+    body::{Body, Bytes, to_bytes},
+    extract::ConnectInfo,
+    http::{Method, Request, Response, header},
+};
+use clap::Parser;
+use image::{ImageFormat, Rgb, RgbImage};
+use min_url_core::{
+    link::DEFAULT_ALLOCATION_RETRIES,
+    store::{
+        MemoryClickStore, MemoryIdempotencyStore, MemoryKeyStore, MemoryRateLimiter,
+        MemorySlugCache, MemorySlugPool, MemorySlugStore,
+    },
+};
+use moka::future::Cache;
+use rand::{SeedableRng, distr::Uniform, rngs::StdRng};
+use redirect_svc::{clicks::ClickRecorder, qr::Logo};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_util::task::TaskTracker;
+use tower::ServiceExt;
+use url::Url;
+
+/// Admin token accepted by write-svc
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Public base URL of redirect-svc
+pub const SELF_DOMAIN: &str = "https://min.url/";
+
+/// Slugs tried by write-svc before giving up on a conflict, as configured by default
+pub const ALLOCATION_RETRIES: u32 = DEFAULT_ALLOCATION_RETRIES;
+
+/// Seconds clients may cache QR codes
+pub const QR_MAX_AGE_SECONDS: u64 = 3600;
+
+/// QR codes rendered at once by redirect-svc
+pub const QR_RENDER_CONCURRENCY: usize = 4;
+
+/// Requests a write-svc client can burst
+pub const RATE_LIMIT_CAPACITY: u32 = 20;
+
+/// Reverse proxies trusted by the services
+pub const TRUSTED_PROXIES: &str = "10.0.0.0/8";
+
+/// Secret keying visitor IDs in redirect-svc
+pub const VISITOR_ID_SECRET: &str = "test-visitor-id-secret";
+
+/// Background task polls needed for a change to reach every service
+const SETTLE_YIELDS: usize = 10;
+
+/// The three services, sharing the same backends
+pub struct Stack {
+    pub cache: Arc<MemorySlugCache>,
+    /// Clicks recorded by redirect-svc
+    pub clicks: Arc<MemoryClickStore>,
+    pub idempotency: Arc<MemoryIdempotencyStore>,
+    pub pool: Arc<MemorySlugPool>,
+    /// QR code render permits of redirect-svc
+    pub qr_renders: Arc<Semaphore>,
+    pub redirect: Router,
+    /// Click recorder of redirect-svc and the tasks writing its clicks
+    recorder: (ClickRecorder, TaskTracker),
+    pub store: Arc<MemorySlugStore>,
+    pub write: Router,
+}
+
+impl Stack {
+    /// Boot the services with the default settings
+    pub async fn new() -> Self {
+        Self::with_allocation_retries(ALLOCATION_RETRIES).await
+    }
+
+    /// Boot the services, write-svc trying the given number of pool slugs per request
+    pub async fn with_allocation_retries(allocation_retries: u32) -> Self {
+        let cache = Arc::new(MemorySlugCache::default());
+        let pool = Arc::new(MemorySlugPool::default());
+        let store = Arc::new(MemorySlugStore::default());
+        let clicks = Arc::new(MemoryClickStore::default());
+        let idempotency = Arc::new(MemoryIdempotencyStore::default());
+
+        // Build write-svc
+        let write = write_svc::app(Arc::new(write_svc::AppState {
+            admin_token_hash: Some(write_svc::hash_key(ADMIN_TOKEN)),
+            allocation_retries,
+            cache: cache.clone(),
+            clicks: clicks.clone(),
+            idempotency: idempotency.clone(),
+            idempotency_ttl_seconds: 60,
+            keys: Arc::new(MemoryKeyStore::default()),
+            pool: pool.clone(),
+            rate_limit: write_svc::RateLimit {
+                capacity: RATE_LIMIT_CAPACITY,
+                refill_per_second: 5,
+            },
+            rate_limiter: Arc::new(MemoryRateLimiter::default()),
+            store: store.clone(),
+            tasks: TaskTracker::new(),
+            trusted_proxies: TRUSTED_PROXIES.parse().unwrap(),
+        }));
+
+        // Build redirect-svc, and let it subscribe to invalidations before any change happens
+        let qr_renders = Arc::new(Semaphore::new(QR_RENDER_CONCURRENCY));
+        let tasks = TaskTracker::new();
+        let recorder = ClickRecorder::spawn(clicks.clone(), VISITOR_ID_SECRET, &tasks);
+        let redirect_state = Arc::new(redirect_svc::AppState {
+            cache: cache.clone(),
+            clicks: recorder.clone(),
+            memory_cache: Cache::builder().max_capacity(100).build(),
+            qr_cache: Cache::builder().max_capacity(100).build(),
+            qr_logos: HashMap::from([("logo".to_string(), Arc::new(test_logo()))]),
+            qr_max_age_seconds: QR_MAX_AGE_SECONDS,
+            qr_renders: qr_renders.clone(),
+            redis_cache_ttl_seconds: 3600,
+            self_domain: Url::parse(SELF_DOMAIN).unwrap(),
+            store: store.clone(),
+            tasks: tasks.clone(),
+            trusted_proxies: TRUSTED_PROXIES.parse().unwrap(),
+        });
+        tokio::spawn(redirect_svc::invalidation::subscribe(
+            redirect_state.clone(),
+        ));
+        let redirect = redirect_svc::app(redirect_state);
+
+        let stack = Self {
+            cache,
+            clicks,
+            idempotency,
+            pool,
+            qr_renders,
+            redirect,
+            recorder: (recorder, tasks),
+            store,
+            write,
+        };
+        stack.settle().await;
+        stack
+    }
+
+    /// Run one slug-filler pass, with a seeded generator
+    pub async fn refill(&self, queue_size: usize, slug_len: usize, batch_size: usize) {
+        let mut rng = StdRng::seed_from_u64(42);
+        let dist = Uniform::new(0, slug_filler::BASE62.len()).unwrap();
+        slug_filler::refill(
+            self.pool.as_ref(),
+            self.store.as_ref(),
+            &mut rng,
+            &dist,
+            queue_size,
+            slug_len,
+            batch_size,
+        )
+        .await
+        .unwrap();
+    }
+
+    /// Shorten a URL on write-svc, anonymously
+    pub async fn shorten(&self, body: Value) -> Response<Body> {
+        send(
+            &self.write,
+            request(Method::POST, "/shorten", Some(body), None),
+        )
+        .await
+    }
+
+    /// Get a path on redirect-svc
+    pub async fn visit(&self, path: &str) -> Response<Body> {
+        send(&self.redirect, request(Method::GET, path, None, None)).await
+    }
+
+    /// Run a minurl-admin command on the shared backends, e.g. `["link", "delete", "docs"]`
+    pub async fn admin(&self, args: &[&str]) -> anyhow::Result<()> {
+        let cli = minurl_admin::Cli::try_parse_from(["minurl-admin"].iter().chain(args))?;
+        let backends = minurl_admin::Backends {
+            cache: self.cache.clone(),
+            pg_pool: None,
+            pool: self.pool.clone(),
+            store: self.store.clone(),
+        };
+        minurl_admin::run(cli.command, &backends).await
+    }
+
+    /// Write the clicks buffered by redirect-svc, which stops recording them
+    pub async fn flush_clicks(&self) {
+        let (recorder, tasks) = &self.recorder;
+        recorder.close();
+        tasks.close();
+        tasks.wait().await;
+    }
+
+    /// Let background tasks (cache writes, invalidations) run, tests use a single-threaded runtime
+    pub async fn settle(&self) {
+        for _ in 0..SETTLE_YIELDS {
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Logo named `logo` in QR codes, a red square
+fn test_logo() -> Logo {
+    let image = RgbImage::from_pixel(8, 8, Rgb([255, 0, 0]));
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    Logo::from_png("logo", png).unwrap()
+}
+
+/// Send a request to a service
+pub async fn send(app: &Router, request: Request<Body>) -> Response<Body> {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+/// Build a request, with a JSON body and an `Authorization: Bearer` header if given
+pub fn request(
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+    token: Option<&str>,
+) -> Request<Body> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    match body {
+        Some(body) => builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+/// Attach the address of the connected peer to a request, as the server does
+pub fn from_peer(mut request: Request<Body>, ip: &str) -> Request<Body> {
+    let ip: IpAddr = ip.parse().unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::new(ip, 40_000)));
+    request
+}
+
+/// Read a response body
+pub async fn body_bytes(response: Response<Body>) -> Bytes {
+    to_bytes(response.into_body(), usize::MAX).await.unwrap()
+}
+
+/// Read a JSON response body
+pub async fn body_json(response: Response<Body>) -> Value {
+    serde_json::from_slice(&body_bytes(response).await).unwrap()
+}
@@ -0,0 +1,144 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, TimeDelta, Utc};
+use integration_tests::Stack;
+use min_url_core::{
+    slug::RedirectType,
+    store::{NewLink, SlugCache, SlugPool, SlugStore},
+};
+
+/// Push slugs to the pool, the last one is allocated first
+async fn fill(stack: &Stack, slugs: &[&str]) {
+    let slugs: Vec<String> = slugs.iter().map(|slug| slug.to_string()).collect();
+    stack.pool.push(&slugs).await.unwrap();
+}
+
+/// Insert a link directly in the store, bypassing the cache
+async fn insert(stack: &Stack, slug: &str, expires_at: Option<DateTime<Utc>>) {
+    let inserted = stack
+        .store
+        .insert(&NewLink {
+            expires_at,
+            owner: None,
+            redirect_type: RedirectType::default(),
+            slug: slug.to_string(),
+            url: format!("https://example.com/{slug}"),
+        })
+        .await
+        .unwrap();
+    assert!(inserted);
+}
+
+#[tokio::test]
+async fn created_links_redirect_until_deleted() {
+    let stack = Stack::new().await;
+    let response = stack.visit("/docs").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Custom slugs are cached, and evicted from memory caches
+    stack
+        .admin(&[
+            "link",
+            "create",
+            "https://example.com/docs",
+            "--slug",
+            "docs",
+        ])
+        .await
+        .unwrap();
+    stack.settle().await;
+    assert!(stack.cache.get("docs").await.unwrap().is_some());
+    let response = stack.visit("/docs").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // Taken and invalid slugs are refused
+    let created = stack
+        .admin(&["link", "create", "https://example.com/", "--slug", "docs"])
+        .await;
+    assert!(created.is_err());
+    let created = stack
+        .admin(&["link", "create", "https://example.com/", "--slug", "d"])
+        .await;
+    assert!(created.is_err());
+    let created = stack.admin(&["link", "create", "ftp://example.com/"]).await;
+    assert!(created.is_err());
+
+    // Disabled links are gone, deleted ones unknown
+    stack.admin(&["link", "disable", "docs"]).await.unwrap();
+    stack.settle().await;
+    let response = stack.visit("/docs").await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    stack.admin(&["link", "delete", "docs"]).await.unwrap();
+    stack.settle().await;
+    assert!(stack.store.get("docs").await.unwrap().is_none());
+    let response = stack.visit("/docs").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(stack.admin(&["link", "delete", "docs"]).await.is_err());
+}
+
+#[tokio::test]
+async fn created_links_take_free_pool_slugs() {
+    let stack = Stack::new().await;
+    insert(&stack, "taken", None).await;
+    fill(&stack, &["free", "taken"]).await;
+
+    // Taken pool slugs are skipped
+    stack
+        .admin(&[
+            "link",
+            "create",
+            "https://example.com/",
+            "--ttl-seconds",
+            "60",
+        ])
+        .await
+        .unwrap();
+    let link = stack.store.get("free").await.unwrap().unwrap();
+    assert!(link.expires_at.is_some());
+    assert_eq!(stack.pool.size().await.unwrap(), 0);
+
+    // Until the pool is empty
+    let created = stack
+        .admin(&["link", "create", "https://example.com/"])
+        .await;
+    assert!(created.is_err());
+}
+
+#[tokio::test]
+async fn pool_is_inspected_and_flushed() {
+    let stack = Stack::new().await;
+    fill(&stack, &["third", "second", "first"]).await;
+    assert_eq!(stack.pool.peek(2).await.unwrap(), ["first", "second"]);
+
+    stack
+        .admin(&["pool", "inspect", "--count", "2"])
+        .await
+        .unwrap();
+    assert_eq!(stack.pool.size().await.unwrap(), 3);
+
+    stack.admin(&["pool", "flush"]).await.unwrap();
+    assert_eq!(stack.pool.size().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn cache_rebuild_caches_live_links() {
+    let stack = Stack::new().await;
+    for slug in ["one", "two", "three"] {
+        insert(&stack, slug, None).await;
+    }
+    insert(&stack, "expired", Some(Utc::now() - TimeDelta::hours(1))).await;
+
+    // Written in batches smaller than the links
+    stack
+        .admin(&["cache", "rebuild", "--batch-size", "2"])
+        .await
+        .unwrap();
+    for slug in ["one", "two", "three"] {
+        let entry = stack.cache.get(slug).await.unwrap().unwrap();
+        assert_eq!(entry.url, format!("https://example.com/{slug}"));
+    }
+    assert!(stack.cache.get("expired").await.unwrap().is_none());
+
+    // Purged slugs are read from the store again
+    stack.admin(&["cache", "purge", "one"]).await.unwrap();
+    assert!(stack.cache.get("one").await.unwrap().is_none());
+}
@@ -0,0 +1,633 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{Method, Request, StatusCode, header},
+};
+use chrono::{TimeDelta, Utc};
+use futures_util::{StreamExt, stream};
+use integration_tests::{
+    ADMIN_TOKEN, QR_MAX_AGE_SECONDS, QR_RENDER_CONCURRENCY, Stack, body_bytes, body_json,
+    from_peer, request, send,
+};
+use min_url_core::{
+    slug::{MAX_SLUG_LEN, RedirectType},
+    store::{NewLink, SlugCache, SlugPool, SlugStore},
+};
+use serde_json::json;
+use std::io;
+
+#[tokio::test]
+async fn custom_slug_redirects() {
+    let stack = Stack::new().await;
+
+    // Create
+    let body = json!({"slug": "docs", "url": "https://example.com/docs", "redirect_type": 308});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "docs");
+
+    // Redirect
+    let response = stack.visit("/docs").await;
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/docs"
+    );
+}
+
+#[tokio::test]
+async fn mini_slug_from_filler_redirects() {
+    let stack = Stack::new().await;
+
+    // Fill the pool
+    stack.refill(10, 6, 10).await;
+    assert_eq!(stack.pool.size().await.unwrap(), 10);
+
+    // Create from the pool
+    let body = json!({"url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let slug = body_json(response).await["slug"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(slug.len(), 6);
+    assert_eq!(stack.pool.size().await.unwrap(), 9);
+
+    // Redirect, with the default redirect type
+    let response = stack.visit(&format!("/{slug}")).await;
+    assert_eq!(response.status(), RedirectType::default().status());
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/");
+}
+
+#[tokio::test]
+async fn created_slug_is_cached() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "cached", "url": "https://example.com/", "ttl_seconds": 60});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    stack.settle().await;
+
+    // Cached with its expiration, for redirect-svc to skip the store
+    let entry = stack.cache.get("cached").await.unwrap().unwrap();
+    assert_eq!(entry.url, "https://example.com/");
+    assert!(entry.ttl_seconds().is_some_and(|ttl| ttl <= 60));
+}
+
+#[tokio::test]
+async fn qr_code_is_rendered() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "qrcode", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // SVG by default
+    let response = stack.visit("/qrcode/qr").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+    assert!(body_bytes(response).await.starts_with(b"<?xml"));
+
+    // Raster formats
+    let response = stack.visit("/qrcode/qr?format=png&size=64").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    assert!(body_bytes(response).await.starts_with(b"\x89PNG"));
+
+    // Print formats
+    let response = stack.visit("/qrcode/qr?format=pdf").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+    let pdf = body_bytes(response).await;
+    assert!(pdf.starts_with(b"%PDF-"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+    let response = stack.visit("/qrcode/qr?format=EPS").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/postscript"
+    );
+    assert!(
+        body_bytes(response)
+            .await
+            .starts_with(b"%!PS-Adobe-3.0 EPSF-3.0")
+    );
+
+    // Text, two rows of modules per line, quiet zone blank
+    let response = stack.visit("/qrcode/qr?format=text&margin=2").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    let version: usize = response.headers()["x-qr-version"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let text = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    let width = 17 + 4 * version + 2 * 2;
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(lines.len(), width.div_ceil(2));
+    assert!(lines.iter().all(|line| line.chars().count() == width));
+    assert!(lines[0].chars().all(|c| c == ' '));
+    assert!(lines[1].contains('█'));
+
+    // Unknown formats are rejected
+    let response = stack.visit("/qrcode/qr?format=bmp").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn qr_code_is_cacheable() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "cacheable", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = json!({"slug": "expiring", "url": "https://example.com/", "ttl_seconds": 60});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = stack.visit("/cacheable/qr?format=png").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        format!("public, max-age={QR_MAX_AGE_SECONDS}")
+    );
+    let etag = response.headers()[header::ETAG].clone();
+    assert!(etag.to_str().unwrap().starts_with('"'));
+
+    // Same image, same tag, other parameters, other tag
+    let response = stack.visit("/cacheable/qr?format=png").await;
+    assert_eq!(response.headers()[header::ETAG], etag);
+    let response = stack.visit("/cacheable/qr?format=png&fg=ff0000").await;
+    assert_ne!(response.headers()[header::ETAG], etag);
+
+    // Revalidation
+    let revalidate = Request::builder()
+        .uri("/cacheable/qr?format=png")
+        .header(header::IF_NONE_MATCH, etag.clone())
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&stack.redirect, revalidate).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag);
+    assert!(body_bytes(response).await.is_empty());
+
+    // Bodies are never compressed, the strong tag matches the bytes sent
+    for format in ["png", "svg"] {
+        let gzip = Request::builder()
+            .uri(format!("/cacheable/qr?format={format}"))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&stack.redirect, gzip).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert!(response.headers().get(header::VARY).is_none());
+    }
+
+    // Cached at most until the slug expires
+    let response = stack.visit("/expiring/qr").await;
+    let cache_control = response.headers()[header::CACHE_CONTROL].to_str().unwrap();
+    let max_age: u64 = cache_control
+        .strip_prefix("public, max-age=")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(max_age <= 60);
+}
+
+#[tokio::test]
+async fn busy_qr_renderers_are_unavailable() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "busy", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Take every render permit
+    let permits = stack
+        .qr_renders
+        .clone()
+        .try_acquire_many_owned(QR_RENDER_CONCURRENCY as u32)
+        .unwrap();
+    let response = stack.visit("/busy/qr").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+    // Redirects are still served
+    let response = stack.visit("/busy").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    drop(permits);
+    let response = stack.visit("/busy/qr").await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn qr_code_version_fits_the_url() {
+    let stack = Stack::new().await;
+
+    let long = "l".repeat(MAX_SLUG_LEN);
+    for slug in ["short", long.as_str()] {
+        let body = json!({"slug": slug, "url": "https://example.com/"});
+        let response = stack.shorten(body).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Smallest version holding the public URL
+    let response = stack.visit("/short/qr").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-qr-version"], "2");
+    let response = stack.visit("/short/qr?ec=h").await;
+    assert_eq!(response.headers()["x-qr-version"], "3");
+
+    // Longest slugs fit too, at the highest error correction level
+    let response = stack.visit(&format!("/{long}/qr?ec=h")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-qr-version"], "17");
+}
+
+#[tokio::test]
+async fn qr_code_is_styled() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "styled", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Colors apply to vector and raster formats
+    let response = stack
+        .visit("/styled/qr?fg=%23112233&bg=ffeedd&margin=0&ec=h")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let svg = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(svg.contains(r##"fill="#112233""##));
+    assert!(svg.contains(r##"fill="#ffeedd""##));
+    let response = stack.visit("/styled/qr?format=jpeg&fg=112233").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Logos are embedded
+    let response = stack.visit("/styled/qr?logo=logo").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let svg = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(svg.contains("data:image/png;base64,"));
+    let response = stack.visit("/styled/qr?format=png&logo=logo").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = stack.visit("/styled/qr?format=pdf&logo=logo").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let pdf = body_bytes(response).await;
+    assert!(pdf.windows(12).any(|window| window == b"/Subtype /Im"));
+
+    // Invalid styles are rejected
+    for query in ["fg=red", "bg=12345", "margin=100", "ec=x", "logo=missing"] {
+        let response = stack.visit(&format!("/styled/qr?{query}")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn unknown_slug_is_not_found() {
+    let stack = Stack::new().await;
+
+    let response = stack.visit("/missing").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = stack.visit("/missing/qr").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn expired_slug_is_gone() {
+    let stack = Stack::new().await;
+
+    // Insert directly, the API refuses past expirations
+    let inserted = stack
+        .store
+        .insert(&NewLink {
+            expires_at: Some(Utc::now() - TimeDelta::seconds(1)),
+            owner: None,
+            redirect_type: RedirectType::default(),
+            slug: "expired".to_string(),
+            url: "https://example.com/".to_string(),
+        })
+        .await
+        .unwrap();
+    assert!(inserted);
+
+    let response = stack.visit("/expired").await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = stack.visit("/expired/qr").await;
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn updated_slug_is_evicted_from_memory_cache() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "moving", "url": "https://example.com/old"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Warm the caches
+    let response = stack.visit("/moving").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/old"
+    );
+    stack.settle().await;
+
+    // Update, then let the invalidation reach redirect-svc
+    let body = json!({"url": "https://example.com/new"});
+    let response = send(
+        &stack.write,
+        request(
+            Method::PATCH,
+            "/links/moving",
+            Some(body),
+            Some(ADMIN_TOKEN),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["url"], "https://example.com/new");
+    stack.settle().await;
+
+    let response = stack.visit("/moving").await;
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "https://example.com/new"
+    );
+}
+
+#[tokio::test]
+async fn deleted_slug_is_not_found() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "deleted", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = stack.visit("/deleted").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    stack.settle().await;
+
+    // Links are managed by their owner only
+    let response = send(
+        &stack.write,
+        request(Method::DELETE, "/links/deleted", None, None),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(
+        &stack.write,
+        request(Method::DELETE, "/links/deleted", None, Some(ADMIN_TOKEN)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    stack.settle().await;
+
+    let response = stack.visit("/deleted").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn imported_links_redirect_and_export() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "taken", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = stack.visit("/imported").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Import, rejected rows are reported without failing the others
+    let upload = "slug,url,redirect_type\n\
+        imported,https://example.com/imported,301\n\
+        taken,https://example.com/taken,\n\
+        invalid,ftp://example.com/,\n\
+        imported,https://example.com/again,\n";
+    let import = Request::builder()
+        .method(Method::POST)
+        .uri("/links")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .header(header::CONTENT_TYPE, "text/csv")
+        .body(Body::from(upload))
+        .unwrap();
+    let response = send(&stack.write, import).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = body_json(response).await;
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["failed"], 3);
+    let rows: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rows, [2, 3, 4]);
+
+    // Imported links are cached, and evicted from memory caches
+    stack.settle().await;
+    assert!(stack.cache.get("imported").await.unwrap().is_some());
+    let response = stack.visit("/imported").await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+    // Export, oldest first
+    let response = send(
+        &stack.write,
+        request(Method::GET, "/links?format=jsonl", None, Some(ADMIN_TOKEN)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body_bytes(response).await;
+    let slugs: Vec<String> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).unwrap()["slug"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(slugs, ["taken", "imported"]);
+
+    // Anonymous callers cannot export
+    let response = send(&stack.write, request(Method::GET, "/links", None, None)).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn import_reports_rows_left_when_the_store_fails() {
+    let stack = Stack::new().await;
+
+    // The store fails once the first batch of 1000 rows is imported
+    let lines = |from: usize, to: usize| {
+        let lines: String = (from..to)
+            .map(|i| format!("{{\"slug\":\"imp{i}\",\"url\":\"https://example.com/\"}}\n"))
+            .collect();
+        Bytes::from(lines)
+    };
+    let chunks = [lines(0, 1000), lines(1000, 1002)];
+    let store = stack.store.clone();
+    let upload = stream::iter(chunks.into_iter().enumerate()).map(move |(i, chunk)| {
+        if i == 1 {
+            store.set_read_only(true);
+        }
+        Ok::<_, io::Error>(chunk)
+    });
+    let import = Request::builder()
+        .method(Method::POST)
+        .uri("/links")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(upload))
+        .unwrap();
+    let response = send(&stack.write, import).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // The report tells which rows were imported
+    let report = body_json(response).await;
+    assert_eq!(report["imported"], 1000);
+    assert_eq!(report["failed"], 2);
+    assert_eq!(report["errors"][0]["row"], 1001);
+    assert_eq!(report["errors"][0]["slug"], "imp1000");
+    assert_eq!(report["errors"][0]["error"], "store unavailable");
+    assert!(stack.store.get("imp999").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn owner_key_reads_its_link_stats() {
+    let stack = Stack::new().await;
+
+    // Create a key for the owner
+    let response = send(
+        &stack.write,
+        request(
+            Method::POST,
+            "/admin/keys",
+            Some(json!({"owner": "alice"})),
+            Some(ADMIN_TOKEN),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let key = body_json(response).await;
+    let token = key["key"].as_str().unwrap();
+
+    // Links are attributed to the key owner
+    let body = json!({"slug": "clicked", "url": "https://example.com/"});
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten", Some(body), Some(token)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["owner"], "alice");
+
+    // Click, twice from the same referrer
+    for referrer in [
+        "https://a.example/",
+        "https://a.example/",
+        "https://b.example/",
+    ] {
+        let visit = Request::builder()
+            .uri("/clicked")
+            .header(header::REFERER, referrer)
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&stack.redirect, visit).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+    stack.flush_clicks().await;
+
+    let response = send(
+        &stack.write,
+        request(Method::GET, "/links/clicked/stats", None, Some(token)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats = body_json(response).await;
+    assert_eq!(stats["total_clicks"], 3);
+    assert_eq!(stats["series"][0]["clicks"], 3);
+    assert_eq!(stats["top_referrers"][0]["referrer"], "https://a.example/");
+    assert_eq!(stats["top_referrers"][0]["clicks"], 2);
+
+    // Revoked keys are rejected
+    let response = send(
+        &stack.write,
+        request(
+            Method::DELETE,
+            &format!("/admin/keys/{}", key["id"]),
+            None,
+            Some(ADMIN_TOKEN),
+        ),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(
+        &stack.write,
+        request(Method::GET, "/links/clicked/stats", None, Some(token)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn visitors_are_identified_by_peer_unless_behind_trusted_proxy() {
+    let stack = Stack::new().await;
+    let body = json!({"slug": "visited", "url": "https://example.com/"});
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten", Some(body), Some(ADMIN_TOKEN)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Forged headers from an untrusted peer are ignored, the trusted proxy reports two visitors
+    for (peer, forwarded_for) in [
+        ("203.0.113.1", "198.51.100.1"),
+        ("203.0.113.1", "198.51.100.2"),
+        ("10.0.0.1", "192.0.2.1, 198.51.100.1"),
+        ("10.0.0.1", "198.51.100.2"),
+    ] {
+        let visit = Request::builder()
+            .uri("/visited")
+            .header("x-forwarded-for", forwarded_for)
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&stack.redirect, from_peer(visit, peer)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    }
+    stack.flush_clicks().await;
+
+    let response = send(
+        &stack.write,
+        request(Method::GET, "/links/visited/stats", None, Some(ADMIN_TOKEN)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats = body_json(response).await;
+    assert_eq!(stats["total_clicks"], 4);
+    assert_eq!(stats["unique_visitors"], 3);
+}
+
+#[tokio::test]
+async fn services_report_readiness() {
+    let stack = Stack::new().await;
+    stack.pool.push(&["pooled".to_string()]).await.unwrap();
+
+    let response = send(&stack.write, request(Method::GET, "/_/ready", None, None)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        body_json(response).await,
+        json!({"postgres": "ok", "redis": "ok", "slug_pool": 1, "status": "ok"})
+    );
+
+    let response = stack.visit("/_/ready").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        body_json(response).await,
+        json!({"postgres": "ok", "redis": "ok", "status": "ok"})
+    );
+}
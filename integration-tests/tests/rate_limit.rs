@@ -0,0 +1,173 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode, header},
+};
+use integration_tests::{
+    ADMIN_TOKEN, RATE_LIMIT_CAPACITY, Stack, body_json, from_peer, request, send,
+};
+use min_url_core::store::SlugCache;
+use serde_json::json;
+
+/// Send an anonymous request to write-svc from the peer, with an `X-Forwarded-For` header if given
+async fn shorten_from(stack: &Stack, peer: &str, forwarded_for: Option<&str>) -> StatusCode {
+    let mut request = request(
+        Method::POST,
+        "/shorten",
+        Some(json!({"url": "ftp://example.com/"})),
+        None,
+    );
+    if let Some(forwarded_for) = forwarded_for {
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", forwarded_for.parse().unwrap());
+    }
+    send(&stack.write, from_peer(request, peer)).await.status()
+}
+
+#[tokio::test]
+async fn anonymous_clients_are_throttled_by_peer() {
+    let stack = Stack::new().await;
+
+    // Forged headers from an untrusted peer share its bucket
+    for i in 0..RATE_LIMIT_CAPACITY {
+        let forwarded_for = format!("198.51.100.{i}");
+        let status = shorten_from(&stack, "203.0.113.1", Some(&forwarded_for)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let request = from_peer(
+        request(Method::POST, "/shorten", Some(json!({})), None),
+        "203.0.113.1",
+    );
+    let response = send(&stack.write, request).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+    // Other clients have their own bucket
+    let status = shorten_from(&stack, "203.0.113.2", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Admins are not limited
+    let admin = Request::builder()
+        .method(Method::GET)
+        .uri("/links/missing")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&stack.write, from_peer(admin, "203.0.113.1")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn clients_behind_trusted_proxies_are_throttled_by_forwarded_address() {
+    let stack = Stack::new().await;
+
+    // The proxy appends the client address, entries left of it are the client's
+    for i in 0..RATE_LIMIT_CAPACITY {
+        let forwarded_for = format!("192.0.2.{i}, 198.51.100.1, 10.0.0.2");
+        let status = shorten_from(&stack, "10.0.0.1", Some(&forwarded_for)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let status = shorten_from(&stack, "10.0.0.1", Some("198.51.100.1")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Another client behind the same proxy is not
+    let status = shorten_from(&stack, "10.0.0.1", Some("198.51.100.2")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn batches_cost_a_token_per_payload() {
+    let stack = Stack::new().await;
+    let batch = |len: usize| {
+        let payloads = vec![json!({"url": "https://example.com/"}); len];
+        from_peer(
+            request(Method::POST, "/shorten/batch", Some(json!(payloads)), None),
+            "203.0.113.1",
+        )
+    };
+
+    // Batches a full bucket could not cover are rejected outright
+    let response = send(&stack.write, batch(RATE_LIMIT_CAPACITY as usize + 1)).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // The rejected batch cost a token, this one 15
+    let response = send(&stack.write, batch(15)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // 4 tokens left, 10 needed
+    let response = send(&stack.write, batch(10)).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+}
+
+#[tokio::test]
+async fn reserved_looking_slugs_cannot_disable_the_limiter() {
+    let stack = Stack::new().await;
+
+    // Slugs cannot take a rate limit bucket key
+    let request = from_peer(
+        request(
+            Method::POST,
+            "/shorten",
+            Some(json!({"url": "https://example.com/", "slug": "rate_limit:ip:203.0.113.1"})),
+            None,
+        ),
+        "203.0.113.1",
+    );
+    let response = send(&stack.write, request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(
+        stack
+            .cache
+            .get("rate_limit:ip:203.0.113.1")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // The client is still throttled
+    for _ in 1..RATE_LIMIT_CAPACITY {
+        let status = shorten_from(&stack, "203.0.113.1", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let status = shorten_from(&stack, "203.0.113.1", None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn invalid_api_keys_are_throttled_by_peer() {
+    let stack = Stack::new().await;
+    let response = send(
+        &stack.write,
+        request(
+            Method::POST,
+            "/admin/keys",
+            Some(json!({"owner": "alice"})),
+            Some(ADMIN_TOKEN),
+        ),
+    )
+    .await;
+    let key = body_json(response).await;
+    let token = key["key"].as_str().unwrap();
+    let shorten_with = |token: &str| {
+        let body = json!({"url": "ftp://example.com/"});
+        from_peer(
+            request(Method::POST, "/shorten", Some(body), Some(token)),
+            "203.0.113.1",
+        )
+    };
+
+    // A fresh token per request does not get a fresh bucket
+    for i in 0..RATE_LIMIT_CAPACITY {
+        let response = send(&stack.write, shorten_with(&format!("mu_forged{i}"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = send(&stack.write, shorten_with("mu_forged")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let status = shorten_from(&stack, "203.0.113.1", None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Valid keys have their own bucket
+    let response = send(&stack.write, shorten_with(token)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,348 @@
+use axum::{
+    body::Body,
+    http::{HeaderValue, Method, Request, StatusCode},
+};
+use integration_tests::{ADMIN_TOKEN, Stack, body_json, request, send};
+use min_url_core::{
+    slug::RedirectType,
+    store::{IdempotencyStore, NewLink, SlugPool, SlugStore},
+};
+use serde_json::json;
+
+/// Take a slug directly in the store
+async fn take(stack: &Stack, slug: &str) {
+    let inserted = stack
+        .store
+        .insert(&NewLink {
+            expires_at: None,
+            owner: None,
+            redirect_type: RedirectType::default(),
+            slug: slug.to_string(),
+            url: "https://example.com/taken".to_string(),
+        })
+        .await
+        .unwrap();
+    assert!(inserted);
+}
+
+/// Push slugs to the pool, the last one is allocated first
+async fn fill(stack: &Stack, slugs: &[&str]) {
+    let slugs: Vec<String> = slugs.iter().map(|slug| slug.to_string()).collect();
+    stack.pool.push(&slugs).await.unwrap();
+}
+
+#[tokio::test]
+async fn taken_custom_slug_conflicts() {
+    let stack = Stack::new().await;
+
+    let body = json!({"slug": "taken", "url": "https://example.com/first"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // The first link is kept
+    let body = json!({"slug": "taken", "url": "https://example.com/second"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let link = stack.store.get("taken").await.unwrap().unwrap();
+    assert_eq!(link.url, "https://example.com/first");
+}
+
+#[tokio::test]
+async fn invalid_requests_are_rejected() {
+    let stack = Stack::new().await;
+
+    for body in [
+        json!({"slug": "ab", "url": "https://example.com/"}),
+        json!({"slug": "a".repeat(257), "url": "https://example.com/"}),
+        json!({"slug": "ftp", "url": "ftp://example.com/"}),
+        json!({"slug": "past", "url": "https://example.com/", "expires_at": "2000-01-01T00:00:00Z"}),
+        json!({"slug": "both", "url": "https://example.com/", "expires_at": "2100-01-01T00:00:00Z", "ttl_seconds": 60}),
+    ] {
+        let response = stack.shorten(body).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    assert_eq!(stack.pool.size().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn taken_pool_slugs_are_skipped() {
+    let stack = Stack::new().await;
+    take(&stack, "taken1").await;
+    take(&stack, "taken2").await;
+    fill(&stack, &["free", "taken1", "taken2"]).await;
+
+    let body = json!({"url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "free");
+    assert_eq!(stack.pool.size().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn empty_pool_is_unavailable() {
+    let stack = Stack::new().await;
+
+    let body = json!({"url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Custom slugs do not need the pool
+    let body = json!({"slug": "custom", "url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn pool_drained_mid_allocation_is_unavailable() {
+    let stack = Stack::new().await;
+    take(&stack, "taken").await;
+    fill(&stack, &["taken"]).await;
+
+    let body = json!({"url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn exhausted_retries_conflict() {
+    let stack = Stack::with_allocation_retries(2).await;
+    for slug in ["taken1", "taken2", "taken3"] {
+        take(&stack, slug).await;
+    }
+    fill(&stack, &["taken1", "taken2", "taken3"]).await;
+
+    // Only as many slugs as retries are consumed
+    let body = json!({"url": "https://example.com/"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(stack.pool.size().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn batch_returns_a_result_per_item() {
+    let stack = Stack::new().await;
+    take(&stack, "taken").await;
+    take(&stack, "taken1").await;
+    fill(&stack, &["free2", "free1", "taken1"]).await;
+
+    let body = json!([
+        {"url": "https://example.com/1"},
+        {"slug": "custom", "url": "https://example.com/custom"},
+        {"slug": "taken", "url": "https://example.com/taken"},
+        {"url": "ftp://example.com/"},
+        {"url": "https://example.com/2"},
+    ]);
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten/batch", Some(body), None),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = body_json(response).await;
+    let statuses: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [201, 201, 409, 400, 201]);
+
+    // Taken pool slugs are replaced by the next ones
+    assert_eq!(results[0]["slug"], "free2");
+    assert_eq!(results[1]["slug"], "custom");
+    assert_eq!(results[4]["slug"], "free1");
+    assert!(results[2].get("slug").is_none());
+    assert_eq!(stack.pool.size().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn batch_rejects_slugs_without_a_partition_per_item() {
+    let stack = Stack::new().await;
+    fill(&stack, &["free"]).await;
+
+    // No partition takes these, they would fail the whole insert
+    let body = json!([
+        {"url": "https://example.com/1"},
+        {"slug": "-abc", "url": "https://example.com/dash"},
+        {"slug": "é-promo", "url": "https://example.com/accent"},
+        {"slug": "custom", "url": "https://example.com/custom"},
+    ]);
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten/batch", Some(body), None),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = body_json(response).await;
+    let statuses: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_u64().unwrap())
+        .collect();
+    assert_eq!(statuses, [201, 400, 400, 201]);
+    assert_eq!(results[0]["slug"], "free");
+    assert_eq!(results[3]["slug"], "custom");
+}
+
+#[tokio::test]
+async fn batch_with_empty_pool_is_partially_unavailable() {
+    let stack = Stack::new().await;
+    fill(&stack, &["free"]).await;
+
+    let body = json!([
+        {"url": "https://example.com/1"},
+        {"url": "https://example.com/2"},
+    ]);
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten/batch", Some(body), None),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = body_json(response).await;
+    assert_eq!(results[0]["status"], 201);
+    assert_eq!(results[0]["slug"], "free");
+    assert_eq!(results[1]["status"], 503);
+}
+
+#[tokio::test]
+async fn dedupe_returns_existing_link() {
+    let stack = Stack::new().await;
+    fill(&stack, &["second", "first"]).await;
+
+    let body = json!({"url": "https://EXAMPLE.com:443/page"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "first");
+
+    // Same normalized URL, no pool slug used
+    let body = json!({"url": "https://example.com/page", "dedupe": true});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_json(response).await["slug"], "first");
+    assert_eq!(stack.pool.size().await.unwrap(), 1);
+
+    // Without the flag, a new slug is allocated
+    let body = json!({"url": "https://example.com/page"});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "second");
+}
+
+#[tokio::test]
+async fn dedupe_is_per_owner() {
+    let stack = Stack::new().await;
+    fill(&stack, &["anonymous", "admin"]).await;
+
+    let body = json!({"url": "https://example.com/", "owner": "someone"});
+    let response = send(
+        &stack.write,
+        request(Method::POST, "/shorten", Some(body), Some(ADMIN_TOKEN)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let body = json!({"url": "https://example.com/", "dedupe": true});
+    let response = stack.shorten(body).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "anonymous");
+}
+
+/// Shorten request with an idempotency key
+fn idempotent(key: &'static str, body: serde_json::Value) -> Request<Body> {
+    let mut request = request(Method::POST, "/shorten", Some(body), None);
+    request
+        .headers_mut()
+        .insert("idempotency-key", HeaderValue::from_static(key));
+    request
+}
+
+#[tokio::test]
+async fn idempotency_key_replays_the_first_response() {
+    let stack = Stack::new().await;
+    fill(&stack, &["second", "first"]).await;
+    let body = json!({"url": "https://example.com/"});
+
+    // Claimed, then completed
+    let response = send(&stack.write, idempotent("retry-1", body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = body_json(response).await;
+    assert_eq!(created["slug"], "first");
+
+    // Retries get the same response, without taking another slug
+    let response = send(&stack.write, idempotent("retry-1", body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await, created);
+    assert_eq!(stack.pool.size().await.unwrap(), 1);
+
+    // The key cannot be reused for another payload
+    let other = json!({"url": "https://example.com/other"});
+    let response = send(&stack.write, idempotent("retry-1", other)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Other keys are independent
+    let response = send(&stack.write, idempotent("retry-2", body)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await["slug"], "second");
+
+    // Empty keys are rejected
+    let response = send(
+        &stack.write,
+        idempotent("", json!({"url": "https://example.com/"})),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn idempotency_key_in_flight_conflicts() {
+    let stack = Stack::new().await;
+    let body = json!({"slug": "in-flight", "url": "https://example.com/"});
+    let response = send(&stack.write, idempotent("retry-1", body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Put the key back in flight, as if the first request had not completed
+    let [key] = stack.idempotency.keys().try_into().unwrap();
+    let record = stack
+        .idempotency
+        .claim(&key, "", 60)
+        .await
+        .unwrap()
+        .unwrap();
+    stack.idempotency.release(&key).await.unwrap();
+    let claimed = stack.idempotency.claim(&key, &record.fingerprint, 60).await;
+    assert!(claimed.unwrap().is_none());
+
+    let response = send(&stack.write, idempotent("retry-1", body)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Released keys are claimed again
+    stack.idempotency.release(&key).await.unwrap();
+    let body = json!({"slug": "retried", "url": "https://example.com/"});
+    let response = send(&stack.write, idempotent("retry-1", body)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn slugs_cannot_take_idempotency_keys() {
+    let stack = Stack::new().await;
+    fill(&stack, &["first"]).await;
+    let body = json!({"url": "https://example.com/"});
+    let response = send(&stack.write, idempotent("retry-1", body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = body_json(response).await;
+
+    // A slug named after the key is rejected
+    let [key] = stack.idempotency.keys().try_into().unwrap();
+    let slug = format!("idempotency:{key}");
+    let response = stack
+        .shorten(json!({"slug": slug, "url": "https://example.com/"}))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Retries still replay
+    let response = send(&stack.write, idempotent("retry-1", body)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(body_json(response).await, created);
+}
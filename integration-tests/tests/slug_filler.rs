@@ -0,0 +1,54 @@
+use integration_tests::Stack;
+use min_url_core::{
+    slug::RedirectType,
+    store::{NewLink, SlugPool, SlugStore},
+};
+use slug_filler::BASE62;
+
+#[tokio::test]
+async fn refill_pushes_a_batch() {
+    let stack = Stack::new().await;
+
+    stack.refill(100, 8, 10).await;
+    assert_eq!(stack.pool.size().await.unwrap(), 10);
+    let slug = stack.pool.pop().await.unwrap().unwrap();
+    assert_eq!(slug.len(), 8);
+    assert!(slug.bytes().all(|b| BASE62.contains(&b)));
+}
+
+#[tokio::test]
+async fn full_pool_is_not_refilled() {
+    let stack = Stack::new().await;
+
+    stack.refill(10, 6, 10).await;
+    stack.refill(10, 6, 10).await;
+    assert_eq!(stack.pool.size().await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn taken_slugs_are_not_pushed() {
+    let stack = Stack::new().await;
+
+    // Take every one-character slug but one
+    for c in BASE62.iter().filter(|c| **c != b'x') {
+        let inserted = stack
+            .store
+            .insert(&NewLink {
+                expires_at: None,
+                owner: None,
+                redirect_type: RedirectType::default(),
+                slug: char::from(*c).to_string(),
+                url: "https://example.com/".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(inserted);
+    }
+
+    stack.refill(1000, 1, 1000).await;
+    let size = stack.pool.size().await.unwrap();
+    assert!(size > 0);
+    for _ in 0..size {
+        assert_eq!(stack.pool.pop().await.unwrap().unwrap(), "x");
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use config::{Config, Environment, File, FileFormat};
+use serde::{Serialize, de::DeserializeOwned};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Service settings, checked once loaded
+pub trait Settings: DeserializeOwned {
+    /// Check values are within bounds and consistent with each other
+    fn validate(&self) -> Result<()>;
+}
+
+/// Configuration file CLI flag, shared by all services
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file
+    #[arg(long, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+}
+
+/// Load settings, by increasing precedence: defaults, the TOML file, environment variables, then CLI flags
+///
+/// Keys are the settings field names, upper-cased for environment variables (e.g. `cache_size` is `CACHE_SIZE`) and kebab-cased for flags (e.g. `--cache-size`).
+pub fn load<T: Settings>(args: &ConfigArgs, flags: &impl Serialize) -> Result<T> {
+    load_from(args, Environment::default(), flags)
+}
+
+/// Load settings, with the given environment variables source
+fn load_from<T: Settings>(
+    args: &ConfigArgs,
+    environment: Environment,
+    flags: &impl Serialize,
+) -> Result<T> {
+    let mut builder = Config::builder();
+
+    // Configuration file, if any
+    if let Some(path) = &args.config {
+        builder = builder.add_source(File::from(path.as_path()).format(FileFormat::Toml));
+    }
+
+    // Environment variables
+    builder = builder.add_source(environment);
+
+    // CLI flags, unset ones leave lower layers untouched
+    let mut flags = serde_json::to_value(flags)?;
+    if let Some(flags) = flags.as_object_mut() {
+        flags.retain(|_, value| !value.is_null());
+    }
+    builder = builder.add_source(Config::try_from(&flags)?);
+
+    // Deserialize and validate
+    let settings: T = builder
+        .build()
+        .and_then(Config::try_deserialize)
+        .context("Invalid configuration")?;
+    settings.validate().context("Invalid configuration")?;
+    Ok(settings)
+}
+
+/// Default public listener
+pub fn default_bind_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 8080))
+}
+
+/// Default Prometheus metrics listener, kept off the public port where `/metrics` would shadow a slug
+pub fn default_metrics_addr() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 9090))
+}
+
+/// Default time to drain in-flight requests, then pending writes, on shutdown
+pub fn default_shutdown_timeout_seconds() -> u64 {
+    10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::ensure;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[derive(Deserialize)]
+    struct TestConfig {
+        #[serde(default = "default_cache_size")]
+        cache_size: u64,
+        database_url: String,
+        #[serde(default = "default_metrics_addr")]
+        metrics_addr: SocketAddr,
+    }
+
+    impl Settings for TestConfig {
+        fn validate(&self) -> Result<()> {
+            ensure!(self.cache_size > 0, "cache_size must be positive");
+            Ok(())
+        }
+    }
+
+    #[derive(Default, Serialize)]
+    struct TestFlags {
+        cache_size: Option<u64>,
+        database_url: Option<String>,
+    }
+
+    fn default_cache_size() -> u64 {
+        100
+    }
+
+    /// Environment variables source, instead of the process environment shared by tests
+    fn environment(vars: &[(&str, &str)]) -> Environment {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Environment::default().source(Some(vars))
+    }
+
+    /// TOML configuration file, removed when dropped
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("min-url-core-{}-{name}.toml", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn args(&self) -> ConfigArgs {
+            ConfigArgs {
+                config: Some(self.0.clone()),
+            }
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_keys() {
+        let args = ConfigArgs { config: None };
+        let env = environment(&[("DATABASE_URL", "postgres://env")]);
+        let config: TestConfig = load_from(&args, env, &TestFlags::default()).unwrap();
+        assert_eq!(config.cache_size, 100);
+        assert_eq!(config.database_url, "postgres://env");
+        assert_eq!(config.metrics_addr, default_metrics_addr());
+    }
+
+    #[test]
+    fn flags_override_environment_overriding_file() {
+        let file = ConfigFile::new(
+            "precedence",
+            "cache_size = 1\ndatabase_url = \"postgres://file\"\nmetrics_addr = \"127.0.0.1:9000\"\n",
+        );
+
+        // File only
+        let config: TestConfig =
+            load_from(&file.args(), environment(&[]), &TestFlags::default()).unwrap();
+        assert_eq!(config.cache_size, 1);
+        assert_eq!(config.database_url, "postgres://file");
+
+        // Environment over file, other keys kept from the file
+        let env = || environment(&[("CACHE_SIZE", "2")]);
+        let config: TestConfig = load_from(&file.args(), env(), &TestFlags::default()).unwrap();
+        assert_eq!(config.cache_size, 2);
+        assert_eq!(config.database_url, "postgres://file");
+        assert_eq!(config.metrics_addr, "127.0.0.1:9000".parse().unwrap());
+
+        // Flags over environment, unset flags leave it untouched
+        let flags = TestFlags {
+            cache_size: Some(3),
+            ..TestFlags::default()
+        };
+        let config: TestConfig = load_from(&file.args(), env(), &flags).unwrap();
+        assert_eq!(config.cache_size, 3);
+        assert_eq!(config.database_url, "postgres://file");
+    }
+
+    #[test]
+    fn invalid_values_are_rejected_with_their_key() {
+        let args = ConfigArgs { config: None };
+
+        // Not a number
+        let env = environment(&[("CACHE_SIZE", "lots"), ("DATABASE_URL", "postgres://env")]);
+        let e = load_from::<TestConfig>(&args, env, &TestFlags::default())
+            .err()
+            .unwrap();
+        assert!(format!("{e:#}").contains("cache_size"), "{e:#}");
+
+        // Out of bounds
+        let env = environment(&[("CACHE_SIZE", "0"), ("DATABASE_URL", "postgres://env")]);
+        let e = load_from::<TestConfig>(&args, env, &TestFlags::default())
+            .err()
+            .unwrap();
+        assert!(
+            format!("{e:#}").contains("cache_size must be positive"),
+            "{e:#}"
+        );
+
+        // Missing
+        let e = load_from::<TestConfig>(&args, environment(&[]), &TestFlags::default())
+            .err()
+            .unwrap();
+        assert!(format!("{e:#}").contains("database_url"), "{e:#}");
+
+        // Unreadable file
+        let args = ConfigArgs {
+            config: Some(PathBuf::from("/nonexistent/min-url.toml")),
+        };
+        let env = environment(&[("DATABASE_URL", "postgres://env")]);
+        assert!(load_from::<TestConfig>(&args, env, &TestFlags::default()).is_err());
+    }
+}
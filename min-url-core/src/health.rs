@@ -0,0 +1,99 @@
+use anyhow::Result;
+use axum::{Json, http::StatusCode, response::IntoResponse};
+use futures_util::future::{BoxFuture, join_all};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::time;
+
+/// Maximum time a dependency check may take before being reported as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Service or dependency status
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Error,
+}
+
+impl CheckStatus {
+    /// Status of a check outcome
+    pub fn of<T>(outcome: &Option<T>) -> Self {
+        match outcome {
+            Some(_) => Self::Ok,
+            None => Self::Error,
+        }
+    }
+}
+
+/// Readiness JSON payload, from the dependency checks of a service
+#[derive(Serialize)]
+pub struct Readiness {
+    /// Status of each dependency, by name
+    #[serde(flatten)]
+    checks: BTreeMap<&'static str, CheckStatus>,
+    status: CheckStatus,
+}
+
+impl Readiness {
+    /// Run the named dependency checks concurrently, ready only if all pass
+    pub async fn check<'a>(
+        checks: impl IntoIterator<Item = (&'static str, BoxFuture<'a, Result<()>>)>,
+    ) -> Self {
+        let (names, probes): (Vec<_>, Vec<_>) = checks.into_iter().unzip();
+        let outcomes = join_all(
+            names
+                .iter()
+                .zip(probes)
+                .map(|(name, probe)| check(name, probe)),
+        )
+        .await;
+        let checks: BTreeMap<_, _> = names
+            .into_iter()
+            .zip(outcomes.iter().map(CheckStatus::of))
+            .collect();
+        let status = if checks.values().all(|status| *status == CheckStatus::Ok) {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Error
+        };
+        Self { checks, status }
+    }
+
+    /// 200 if ready, 503 otherwise
+    pub fn code(&self) -> StatusCode {
+        match self.status {
+            CheckStatus::Ok => StatusCode::OK,
+            CheckStatus::Error => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Liveness JSON payload
+#[derive(Serialize)]
+struct HealthPayload {
+    status: CheckStatus,
+}
+
+/// Liveness handler, the process answers, dependencies are not checked
+pub async fn handle_health_get() -> impl IntoResponse {
+    Json(HealthPayload {
+        status: CheckStatus::Ok,
+    })
+}
+
+/// Run a dependency check with a timeout, logging failures
+pub async fn check<T>(name: &str, probe: impl Future<Output = Result<T>>) -> Option<T> {
+    match time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            tracing::warn!("{name} readiness check failed: {e:?}");
+            None
+        }
+        Err(_) => {
+            tracing::warn!("{name} readiness check timed out");
+            None
+        }
+    }
+}
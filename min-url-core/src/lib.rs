@@ -0,0 +1,11 @@
+//! Building blocks shared by the min-url-rs services: configuration, storage pools and abstractions, slug model, link validation and allocation, client addresses, telemetry and lifecycle.
+
+pub mod config;
+pub mod health;
+pub mod link;
+pub mod net;
+pub mod pools;
+pub mod shutdown;
+pub mod slug;
+pub mod store;
+pub mod telemetry;
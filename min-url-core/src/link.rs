@@ -0,0 +1,96 @@
+use crate::slug::{MAX_SLUG_LEN, MIN_CUSTOM_SLUG_LEN};
+use crate::store::{NewLink, SlugPool, SlugStore};
+use anyhow::{Result, bail, ensure};
+use chrono::{DateTime, TimeDelta, Utc};
+use url::Url;
+
+/// Default pool slugs tried before giving up on a conflict
+pub const DEFAULT_ALLOCATION_RETRIES: u32 = 6;
+
+/// Mini-slug allocation failure
+pub enum AllocationError {
+    /// Pool empty
+    NoSlug,
+    /// Every slug tried was already taken
+    Conflict,
+    /// Pool or store unavailable
+    Unavailable(anyhow::Error),
+}
+
+/// Whether the URL is HTTP(S)
+pub fn is_http_url(url: &Url) -> bool {
+    url.scheme() == "http" || url.scheme() == "https"
+}
+
+/// Check a custom slug fits, has a partition, and has no `:` which separates Redis key namespaces
+pub fn check_custom_slug(slug: &str) -> Result<()> {
+    ensure!(
+        (MIN_CUSTOM_SLUG_LEN..=MAX_SLUG_LEN).contains(&slug.len()),
+        "slug must be between {MIN_CUSTOM_SLUG_LEN} and {MAX_SLUG_LEN} characters"
+    );
+    ensure!(
+        slug.starts_with(|c: char| c.is_ascii_alphanumeric()),
+        "slug must start with a letter or a digit"
+    );
+    ensure!(!slug.contains(':'), "slug must not contain ':'");
+    Ok(())
+}
+
+/// Resolve an absolute or relative expiration date, which must be in the future
+pub fn resolve_expiration(
+    expires_at: Option<DateTime<Utc>>,
+    ttl_seconds: Option<u64>,
+) -> Result<Option<DateTime<Utc>>> {
+    let expires_at = match (expires_at, ttl_seconds) {
+        (Some(_), Some(_)) => bail!("expires_at and ttl_seconds are exclusive"),
+        (Some(expires_at), None) => expires_at,
+        (None, Some(ttl)) => match i64::try_from(ttl)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+        {
+            Some(expires_at) => expires_at,
+            None => bail!("ttl_seconds is out of range"),
+        },
+        (None, None) => return Ok(None),
+    };
+
+    // Check if expiration date is in the future
+    ensure!(expires_at > Utc::now(), "expires_at must be in the future");
+
+    Ok(Some(expires_at))
+}
+
+/// Insert the link with a mini-slug from the pool, trying up to `retries` slugs if they are taken
+#[tracing::instrument(name = "allocate_mini_slug", skip(pool, store, link))]
+pub async fn allocate(
+    pool: &dyn SlugPool,
+    store: &dyn SlugStore,
+    link: &mut NewLink,
+    retries: u32,
+) -> Result<(), AllocationError> {
+    for retry in 0..retries {
+        // 1, pop slug from the pool
+        link.slug = match pool.pop().await {
+            Ok(Some(slug)) => slug,
+            Ok(None) => return Err(AllocationError::NoSlug),
+            Err(e) => return Err(AllocationError::Unavailable(e)),
+        };
+
+        // 2, try insert into Postgres
+        metrics::counter!("write_allocations_total").increment(1);
+        match store.insert(link).await {
+            // If inserted, done
+            Ok(true) => return Ok(()),
+            // If conflict, retry
+            Ok(false) => {
+                tracing::debug!("Slug {} already exists, retrying ({retry})", link.slug);
+                metrics::counter!("write_retry_total").increment(1);
+            }
+            Err(e) => return Err(AllocationError::Unavailable(e)),
+        }
+    }
+
+    // 3, exhausted all retries
+    Err(AllocationError::Conflict)
+}
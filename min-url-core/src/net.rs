@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, HeaderName};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, de::Error};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Header in which reverse proxies append the address of their client
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Reverse proxies trusted to report client addresses in `X-Forwarded-For`, none by default
+///
+/// Parsed from comma-separated addresses or CIDR networks, e.g. `10.0.0.0/8, 192.168.1.1`.
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    /// Address of the client, the peer unless it is a trusted proxy
+    ///
+    /// Behind trusted proxies, `X-Forwarded-For` is read from the right, each proxy appending its peer: the first untrusted address is the client. Entries left of it are supplied by the client and ignored.
+    pub fn client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.ip();
+        if !self.contains(client) {
+            return Some(client);
+        }
+        let forwarded = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .rev();
+        for hop in forwarded {
+            // If a hop is garbled, the last proxy reporting it is the closest known address
+            let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = hop;
+            if !self.contains(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    /// Whether the address belongs to a trusted proxy
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|net| net.contains(&ip))
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| {
+                // Bare addresses are single-host networks
+                proxy
+                    .parse::<IpNet>()
+                    .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                    .with_context(|| format!("invalid proxy address {proxy}"))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TrustedProxies {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e: anyhow::Error| D::Error::custom(format!("{e:#}")))
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use deadpool_postgres::{
+    ManagerConfig, Pool as PostgresPool, RecyclingMethod, Runtime as PgRuntime,
+    tokio_postgres::NoTls,
+};
+use deadpool_redis::{Config as RedisConfig, Pool as RedisPool, Runtime as RedisRuntime};
+
+/// Connection pools to the storage backends
+pub struct Pools {
+    pub pg_pool: PostgresPool,
+    pub redis_pool: RedisPool,
+}
+
+impl Pools {
+    /// Build both pools, connections are opened lazily
+    pub fn connect(database_url: &str, redis_url: &str) -> Result<Self> {
+        Ok(Self {
+            pg_pool: postgres_pool(database_url)?,
+            redis_pool: redis_pool(redis_url)?,
+        })
+    }
+}
+
+/// Build a PostgreSQL pool
+pub fn postgres_pool(url: &str) -> Result<PostgresPool> {
+    let mut pg_cfg = deadpool_postgres::Config::new();
+    pg_cfg.manager = Some(ManagerConfig {
+        recycling_method: RecyclingMethod::Fast,
+    });
+    pg_cfg.url = Some(url.to_string());
+    Ok(pg_cfg.create_pool(Some(PgRuntime::Tokio1), NoTls)?)
+}
+
+/// Build a Redis pool
+pub fn redis_pool(url: &str) -> Result<RedisPool> {
+    let redis_cfg = RedisConfig::from_url(url);
+    Ok(redis_cfg.create_pool(Some(RedisRuntime::Tokio1))?)
+}
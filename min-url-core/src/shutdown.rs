@@ -0,0 +1,62 @@
+use anyhow::Result;
+use axum::Router;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::signal::{self, unix::SignalKind};
+use tokio::time;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Token cancelled on SIGTERM or Ctrl+C
+pub fn shutdown_token() -> Result<CancellationToken> {
+    let shutdown = CancellationToken::new();
+    let mut sigterm = signal::unix::signal(SignalKind::terminate())?;
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            tokio::select! {
+                _ = sigterm.recv() => tracing::info!("Received SIGTERM, shutting down"),
+                _ = signal::ctrl_c() => tracing::info!("Received Ctrl+C, shutting down"),
+            }
+            shutdown.cancel();
+        }
+    });
+    Ok(shutdown)
+}
+
+/// Serve the app until shutdown, then drain in-flight requests up to the deadline
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: &CancellationToken,
+    timeout: Duration,
+) -> Result<()> {
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+
+    // Drain in-flight requests, up to the deadline
+    let deadline = async {
+        shutdown.cancelled().await;
+        time::sleep(timeout).await;
+    };
+    tokio::select! {
+        res = server.into_future() => res?,
+        _ = deadline => tracing::warn!("Shutdown deadline reached, dropping in-flight requests"),
+    }
+
+    Ok(())
+}
+
+/// Wait for fire & forget tasks, up to the deadline
+pub async fn wait_tasks(tasks: &TaskTracker, timeout: Duration) {
+    tasks.close();
+    if time::timeout(timeout, tasks.wait()).await.is_err() {
+        tracing::warn!(
+            "Shutdown deadline reached, dropping {} pending writes",
+            tasks.len()
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Redis list of pre-generated mini-slugs, filled by slug-filler and consumed by write-svc
+pub const SLUG_POOL: &str = "slug_pool";
+
+/// Redis pub/sub channel on which write-svc publishes changed slugs, for redirect-svc to evict them
+pub const INVALIDATION_CHANNEL: &str = "slug_invalidation";
+
+/// Prefix of slug cache keys in Redis, keeping slugs apart from rate limit buckets and idempotency keys
+pub const SLUG_KEY_PREFIX: &str = "slug:";
+
+/// Shortest custom slug, one and two characters are left for reserved prefixes like `/_/`
+pub const MIN_CUSTOM_SLUG_LEN: usize = 3;
+
+/// Longest slug, as stored in PostgreSQL
+pub const MAX_SLUG_LEN: usize = 256;
+
+/// Slug entry, as cached in Redis and memory
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SlugEntry {
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub redirect_type: RedirectType,
+    pub url: String,
+}
+
+/// Redis key caching the slug
+pub fn cache_key(slug: &str) -> String {
+    format!("{SLUG_KEY_PREFIX}{slug}")
+}
+
+impl SlugEntry {
+    /// Parse a Redis value, legacy entries hold the bare URL
+    pub fn from_redis(value: String) -> Result<Self> {
+        if !value.starts_with('{') {
+            return Ok(Self {
+                expires_at: None,
+                redirect_type: RedirectType::default(),
+                url: value,
+            });
+        }
+        Ok(serde_json::from_str(&value)?)
+    }
+
+    /// Whether the slug expiration date is past
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// Seconds until the slug expires, if it ever does
+    pub fn ttl_seconds(&self) -> Option<i64> {
+        self.expires_at
+            .map(|expires_at| (expires_at - Utc::now()).num_seconds().max(1))
+    }
+}
+
+/// HTTP redirect status code, serialized as the numeric code
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectType {
+    /// 301, permanent, method may change
+    MovedPermanently,
+    /// 302, temporary, method may change
+    Found,
+    /// 303, temporary, method changed to GET, as sent before redirect types existed
+    #[default]
+    SeeOther,
+    /// 307, temporary, method preserved
+    TemporaryRedirect,
+    /// 308, permanent, method preserved
+    PermanentRedirect,
+}
+
+impl TryFrom<u16> for RedirectType {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            301 => Ok(Self::MovedPermanently),
+            302 => Ok(Self::Found),
+            303 => Ok(Self::SeeOther),
+            307 => Ok(Self::TemporaryRedirect),
+            308 => Ok(Self::PermanentRedirect),
+            _ => Err(format!("unsupported redirect type {code}")),
+        }
+    }
+}
+
+impl RedirectType {
+    /// HTTP status code of the redirect response
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            Self::Found => StatusCode::FOUND,
+            Self::SeeOther => StatusCode::SEE_OTHER,
+            Self::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            Self::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+impl From<RedirectType> for u16 {
+    fn from(redirect_type: RedirectType) -> Self {
+        match redirect_type {
+            RedirectType::MovedPermanently => 301,
+            RedirectType::Found => 302,
+            RedirectType::SeeOther => 303,
+            RedirectType::TemporaryRedirect => 307,
+            RedirectType::PermanentRedirect => 308,
+        }
+    }
+}
+
+/// First character of a slug, used as partition key
+pub fn first_char(slug: &str) -> &str {
+    &slug[..slug.chars().next().map_or(0, char::len_utf8)]
+}
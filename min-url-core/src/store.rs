@@ -0,0 +1,319 @@
+mod memory;
+mod postgres;
+mod redis;
+
+pub use memory::{
+    MemoryClickStore, MemoryIdempotencyStore, MemoryKeyStore, MemoryRateLimiter, MemorySlugCache,
+    MemorySlugPool, MemorySlugStore,
+};
+pub use postgres::{PgClickStore, PgKeyStore, PgSlugStore};
+pub use redis::{RedisIdempotencyStore, RedisRateLimiter, RedisSlugCache, RedisSlugPool};
+
+use crate::slug::{RedirectType, SlugEntry};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Referrers returned in click stats rankings
+pub const TOP_REFERRERS: usize = 10;
+
+/// Link, as stored
+#[derive(Clone, Serialize)]
+pub struct Link {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub owner: Option<String>,
+    pub redirect_type: RedirectType,
+    pub slug: String,
+    pub url: String,
+}
+
+impl Link {
+    /// Entry to cache for redirects
+    pub fn entry(&self) -> SlugEntry {
+        SlugEntry {
+            expires_at: self.expires_at,
+            redirect_type: self.redirect_type,
+            url: self.url.clone(),
+        }
+    }
+}
+
+/// Link to create
+pub struct NewLink {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub owner: Option<String>,
+    pub redirect_type: RedirectType,
+    pub slug: String,
+    pub url: String,
+}
+
+impl NewLink {
+    /// Entry to cache for redirects
+    pub fn entry(&self) -> SlugEntry {
+        SlugEntry {
+            expires_at: self.expires_at,
+            redirect_type: self.redirect_type,
+            url: self.url.clone(),
+        }
+    }
+}
+
+/// Link changes, `None` fields are left untouched
+#[derive(Default)]
+pub struct LinkUpdate {
+    /// `Some(None)` clears the expiration
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub redirect_type: Option<RedirectType>,
+    pub url: Option<String>,
+}
+
+/// Links to export, all links by default
+#[derive(Default)]
+pub struct LinkFilter {
+    /// Created at or after
+    pub from: Option<DateTime<Utc>>,
+    pub owner: Option<String>,
+    /// Created before
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl LinkFilter {
+    /// Whether a link passes the filter
+    pub fn matches(&self, link: &Link) -> bool {
+        self.owner
+            .as_ref()
+            .is_none_or(|owner| link.owner.as_ref() == Some(owner))
+            && self.from.is_none_or(|from| link.created_at >= from)
+            && self.to.is_none_or(|to| link.created_at < to)
+    }
+}
+
+/// Durable slug storage, source of truth for links
+#[async_trait]
+pub trait SlugStore: Send + Sync {
+    /// Insert a link, returns false if the slug is taken
+    async fn insert(&self, link: &NewLink) -> Result<bool>;
+
+    /// Insert links in a single statement, returns for each whether it was inserted, false if the slug is taken or repeated
+    async fn insert_many(&self, links: &[NewLink]) -> Result<Vec<bool>>;
+
+    /// Insert links in bulk, returns for each whether it was inserted, false if the slug is taken or repeated
+    async fn import(&self, links: &[NewLink]) -> Result<Vec<bool>>;
+
+    /// Stream links, oldest first
+    async fn export(&self, filter: &LinkFilter) -> Result<BoxStream<'static, Result<Link>>>;
+
+    /// Get a link
+    async fn get(&self, slug: &str) -> Result<Option<Link>>;
+
+    /// Oldest live link of an owner to the URL, for deduplication
+    async fn find_by_url(&self, url: &str, owner: Option<&str>) -> Result<Option<Link>>;
+
+    /// Update a link, returns it as updated, `None` if unknown
+    async fn update(&self, slug: &str, update: &LinkUpdate) -> Result<Option<Link>>;
+
+    /// Delete a link, returns false if unknown
+    async fn delete(&self, slug: &str) -> Result<bool>;
+
+    /// Slugs of the list which are already taken
+    async fn taken(&self, slugs: &[String]) -> Result<HashSet<String>>;
+
+    /// Check the store answers
+    async fn ping(&self) -> Result<()>;
+}
+
+/// Shared slug cache in front of the store, with change notifications for per-instance caches
+#[async_trait]
+pub trait SlugCache: Send + Sync {
+    /// Get a cached entry
+    async fn get(&self, slug: &str) -> Result<Option<SlugEntry>>;
+
+    /// Cache an entry, expiring with the slug, or after `max_ttl_seconds` if sooner
+    async fn set(&self, slug: &str, entry: &SlugEntry, max_ttl_seconds: Option<u64>) -> Result<()>;
+
+    /// Cache entries at once, each expiring with its slug
+    async fn set_many(&self, entries: &[(String, SlugEntry)]) -> Result<()>;
+
+    /// Drop an entry and notify subscribers
+    async fn invalidate(&self, slug: &str) -> Result<()>;
+
+    /// Notify subscribers the slug changed
+    async fn publish(&self, slug: &str) -> Result<()>;
+
+    /// Stream of changed slugs, ends when the connection drops
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<String>>>;
+
+    /// Check the cache answers
+    async fn ping(&self) -> Result<()>;
+}
+
+/// Pool of pre-generated mini-slugs, not yet taken
+#[async_trait]
+pub trait SlugPool: Send + Sync {
+    /// Take a slug, `None` if the pool is empty
+    async fn pop(&self) -> Result<Option<String>>;
+
+    /// Take up to `count` slugs, fewer if the pool runs out
+    async fn pop_many(&self, count: usize) -> Result<Vec<String>>;
+
+    /// Add slugs
+    async fn push(&self, slugs: &[String]) -> Result<()>;
+
+    /// Slugs left
+    async fn size(&self) -> Result<usize>;
+
+    /// Up to `count` slugs, in allocation order, without taking them
+    async fn peek(&self, count: usize) -> Result<Vec<String>>;
+
+    /// Remove every slug, returns how many there were
+    async fn clear(&self) -> Result<usize>;
+}
+
+/// API key, only its hash is stored
+pub struct ApiKey {
+    pub created_at: DateTime<Utc>,
+    pub id: i64,
+    pub owner: String,
+}
+
+/// API keys, by hash
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// Create a key for the owner
+    async fn create(&self, owner: &str, key_hash: &[u8]) -> Result<ApiKey>;
+
+    /// Owner of a key, `None` if unknown or revoked
+    async fn owner(&self, key_hash: &[u8]) -> Result<Option<String>>;
+
+    /// Revoke a key, kept for audit, returns false if unknown or already revoked
+    async fn revoke(&self, id: i64) -> Result<bool>;
+}
+
+/// Click to record
+pub struct NewClick {
+    pub clicked_at: DateTime<Utc>,
+    pub country: Option<String>,
+    pub referrer: Option<String>,
+    pub slug: String,
+    pub user_agent: Option<String>,
+    /// Pseudonymous visitor ID
+    pub visitor: Option<String>,
+}
+
+/// Click stats time series bucket width
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    #[default]
+    Day,
+}
+
+/// Click stats query, the range is unbounded by default
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    #[serde(default)]
+    pub bucket: Bucket,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl StatsQuery {
+    /// Whether a click date is within the range
+    pub fn contains(&self, clicked_at: DateTime<Utc>) -> bool {
+        self.from.is_none_or(|from| clicked_at >= from) && self.to.is_none_or(|to| clicked_at < to)
+    }
+}
+
+/// Clicks of a link
+#[derive(Serialize)]
+pub struct ClickStats {
+    /// Oldest bucket first
+    pub series: Vec<SeriesPoint>,
+    /// Most clicks first, at most `TOP_REFERRERS`
+    pub top_referrers: Vec<ReferrerCount>,
+    pub total_clicks: i64,
+    pub unique_visitors: i64,
+}
+
+/// Clicks within a time bucket
+#[derive(Serialize)]
+pub struct SeriesPoint {
+    pub clicks: i64,
+    pub start: DateTime<Utc>,
+    pub unique_visitors: i64,
+}
+
+/// Clicks from a referrer
+#[derive(Serialize)]
+pub struct ReferrerCount {
+    pub clicks: i64,
+    pub referrer: String,
+}
+
+/// Recorded clicks, aggregated into stats
+#[async_trait]
+pub trait ClickStore: Send + Sync {
+    /// Insert clicks in a single statement
+    async fn insert(&self, clicks: &[NewClick]) -> Result<()>;
+
+    /// Stats of a slug within the query range
+    async fn stats(&self, slug: &str, query: &StatsQuery) -> Result<ClickStats>;
+}
+
+/// Token buckets, one per client key, shared across replicas
+#[async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Take `tokens` tokens at once, returns the milliseconds to wait if the bucket holds fewer
+    ///
+    /// Buckets hold up to `capacity` tokens and regain `refill_per_second`. Nothing is taken when throttled.
+    async fn take(
+        &self,
+        key: &str,
+        tokens: u32,
+        capacity: u32,
+        refill_per_second: u32,
+    ) -> Result<Option<u64>>;
+}
+
+/// Idempotency key record
+#[derive(Deserialize, Serialize)]
+pub struct IdempotencyRecord {
+    /// Hash of the request payload, a key cannot be reused for another request
+    pub fingerprint: String,
+    /// `None` while the first request is in flight
+    pub response: Option<StoredResponse>,
+}
+
+/// Response replayed to retries
+#[derive(Deserialize, Serialize)]
+pub struct StoredResponse {
+    pub body: Value,
+    pub status: u16,
+}
+
+/// Idempotency keys, expiring
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Claim a key with an in-flight record for `ttl_seconds`, returns the existing record if already claimed
+    async fn claim(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>>;
+
+    /// Store the record of a completed request
+    async fn complete(&self, key: &str, record: &IdempotencyRecord, ttl_seconds: u64)
+    -> Result<()>;
+
+    /// Drop a claim
+    async fn release(&self, key: &str) -> Result<()>;
+}
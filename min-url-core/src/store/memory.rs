@@ -0,0 +1,495 @@
+use super::{
+    ApiKey, Bucket, ClickStats, ClickStore, IdempotencyRecord, IdempotencyStore, KeyStore, Link,
+    LinkFilter, LinkUpdate, NewClick, NewLink, RateLimiter, ReferrerCount, SeriesPoint, SlugCache,
+    SlugPool, SlugStore, StatsQuery, TOP_REFERRERS,
+};
+use crate::slug::SlugEntry;
+use anyhow::{Result, ensure};
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use futures_util::{StreamExt, stream::BoxStream};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Changes buffered per subscriber before the oldest are dropped
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// In-memory slug store, for tests
+#[derive(Default)]
+pub struct MemorySlugStore {
+    links: Mutex<HashMap<String, Link>>,
+    /// Writes fail, like on a database outage
+    read_only: AtomicBool,
+}
+
+impl MemorySlugStore {
+    /// Make writes fail, or succeed again
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Fail if writes are disabled
+    fn check_writable(&self) -> Result<()> {
+        ensure!(
+            !self.read_only.load(Ordering::Relaxed),
+            "store is read-only"
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SlugStore for MemorySlugStore {
+    async fn insert(&self, link: &NewLink) -> Result<bool> {
+        self.check_writable()?;
+        let mut links = self.links.lock().unwrap();
+        if links.contains_key(&link.slug) {
+            return Ok(false);
+        }
+        links.insert(
+            link.slug.clone(),
+            Link {
+                created_at: Utc::now(),
+                expires_at: link.expires_at,
+                owner: link.owner.clone(),
+                redirect_type: link.redirect_type,
+                slug: link.slug.clone(),
+                url: link.url.clone(),
+            },
+        );
+        Ok(true)
+    }
+
+    async fn insert_many(&self, links: &[NewLink]) -> Result<Vec<bool>> {
+        let mut inserted = Vec::with_capacity(links.len());
+        for link in links {
+            inserted.push(self.insert(link).await?);
+        }
+        Ok(inserted)
+    }
+
+    async fn import(&self, links: &[NewLink]) -> Result<Vec<bool>> {
+        let mut inserted = Vec::with_capacity(links.len());
+        for link in links {
+            inserted.push(self.insert(link).await?);
+        }
+        Ok(inserted)
+    }
+
+    async fn export(&self, filter: &LinkFilter) -> Result<BoxStream<'static, Result<Link>>> {
+        let mut links: Vec<Link> = self
+            .links
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|link| filter.matches(link))
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| (a.created_at, &a.slug).cmp(&(b.created_at, &b.slug)));
+        Ok(futures_util::stream::iter(links.into_iter().map(Ok)).boxed())
+    }
+
+    async fn get(&self, slug: &str) -> Result<Option<Link>> {
+        Ok(self.links.lock().unwrap().get(slug).cloned())
+    }
+
+    async fn find_by_url(&self, url: &str, owner: Option<&str>) -> Result<Option<Link>> {
+        let now = Utc::now();
+        Ok(self
+            .links
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|link| {
+                link.url == url
+                    && link.owner.as_deref() == owner
+                    && link.expires_at.is_none_or(|expires_at| expires_at > now)
+            })
+            .min_by(|a, b| (a.created_at, &a.slug).cmp(&(b.created_at, &b.slug)))
+            .cloned())
+    }
+
+    async fn update(&self, slug: &str, update: &LinkUpdate) -> Result<Option<Link>> {
+        self.check_writable()?;
+        let mut links = self.links.lock().unwrap();
+        let Some(link) = links.get_mut(slug) else {
+            return Ok(None);
+        };
+        if let Some(expires_at) = update.expires_at {
+            link.expires_at = expires_at;
+        }
+        if let Some(redirect_type) = update.redirect_type {
+            link.redirect_type = redirect_type;
+        }
+        if let Some(url) = &update.url {
+            link.url = url.clone();
+        }
+        Ok(Some(link.clone()))
+    }
+
+    async fn delete(&self, slug: &str) -> Result<bool> {
+        self.check_writable()?;
+        Ok(self.links.lock().unwrap().remove(slug).is_some())
+    }
+
+    async fn taken(&self, slugs: &[String]) -> Result<HashSet<String>> {
+        let links = self.links.lock().unwrap();
+        Ok(slugs
+            .iter()
+            .filter(|slug| links.contains_key(*slug))
+            .cloned()
+            .collect())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// In-memory slug cache, for tests, notifications reach subscribers of the same instance
+pub struct MemorySlugCache {
+    /// Entry and forced expiration, by slug
+    entries: Mutex<HashMap<String, (SlugEntry, Option<Instant>)>>,
+    tx: broadcast::Sender<String>,
+}
+
+impl Default for MemorySlugCache {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::default(),
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+#[async_trait]
+impl SlugCache for MemorySlugCache {
+    async fn get(&self, slug: &str) -> Result<Option<SlugEntry>> {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Expire like Redis would
+        let now = Instant::now();
+        if entries.get(slug).is_some_and(|(entry, expires_at)| {
+            entry.is_expired() || expires_at.is_some_and(|expires_at| expires_at <= now)
+        }) {
+            entries.remove(slug);
+        }
+        Ok(entries.get(slug).map(|(entry, _)| entry.clone()))
+    }
+
+    async fn set(&self, slug: &str, entry: &SlugEntry, max_ttl_seconds: Option<u64>) -> Result<()> {
+        let expires_at = max_ttl_seconds.map(|ttl| Instant::now() + Duration::from_secs(ttl));
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(slug.to_string(), (entry.clone(), expires_at));
+        Ok(())
+    }
+
+    async fn set_many(&self, entries: &[(String, SlugEntry)]) -> Result<()> {
+        let mut cached = self.entries.lock().unwrap();
+        for (slug, entry) in entries {
+            cached.insert(slug.clone(), (entry.clone(), None));
+        }
+        Ok(())
+    }
+
+    async fn invalidate(&self, slug: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(slug);
+        self.publish(slug).await
+    }
+
+    async fn publish(&self, slug: &str) -> Result<()> {
+        // Nobody listening is not an error
+        let _ = self.tx.send(slug.to_string());
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<String>>> {
+        let rx = self.tx.subscribe();
+        Ok(futures_util::stream::unfold(rx, |mut rx| async move {
+            let slug = rx.recv().await.map_err(anyhow::Error::from);
+            Some((slug, rx))
+        })
+        .boxed())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// In-memory slug pool, for tests, popping the last pushed slug first like the Redis list
+#[derive(Default)]
+pub struct MemorySlugPool {
+    slugs: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl SlugPool for MemorySlugPool {
+    async fn pop(&self) -> Result<Option<String>> {
+        Ok(self.slugs.lock().unwrap().pop())
+    }
+
+    async fn pop_many(&self, count: usize) -> Result<Vec<String>> {
+        let mut slugs = self.slugs.lock().unwrap();
+        let start = slugs.len().saturating_sub(count);
+        Ok(slugs.drain(start..).rev().collect())
+    }
+
+    async fn push(&self, slugs: &[String]) -> Result<()> {
+        self.slugs.lock().unwrap().extend_from_slice(slugs);
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<usize> {
+        Ok(self.slugs.lock().unwrap().len())
+    }
+
+    async fn peek(&self, count: usize) -> Result<Vec<String>> {
+        let slugs = self.slugs.lock().unwrap();
+        Ok(slugs.iter().rev().take(count).cloned().collect())
+    }
+
+    async fn clear(&self) -> Result<usize> {
+        Ok(self.slugs.lock().unwrap().drain(..).count())
+    }
+}
+
+/// API key, as kept by `MemoryKeyStore`
+struct StoredKey {
+    key_hash: Vec<u8>,
+    owner: String,
+    revoked: bool,
+}
+
+/// In-memory API key store, for tests, IDs count from 1
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    keys: Mutex<Vec<StoredKey>>,
+}
+
+#[async_trait]
+impl KeyStore for MemoryKeyStore {
+    async fn create(&self, owner: &str, key_hash: &[u8]) -> Result<ApiKey> {
+        let mut keys = self.keys.lock().unwrap();
+        let created_at = Utc::now();
+        keys.push(StoredKey {
+            key_hash: key_hash.to_vec(),
+            owner: owner.to_string(),
+            revoked: false,
+        });
+        Ok(ApiKey {
+            created_at,
+            id: keys.len() as i64,
+            owner: owner.to_string(),
+        })
+    }
+
+    async fn owner(&self, key_hash: &[u8]) -> Result<Option<String>> {
+        Ok(self
+            .keys
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|key| !key.revoked && key.key_hash == key_hash)
+            .map(|key| key.owner.clone()))
+    }
+
+    async fn revoke(&self, id: i64) -> Result<bool> {
+        let mut keys = self.keys.lock().unwrap();
+        let key = usize::try_from(id - 1)
+            .ok()
+            .and_then(|i| keys.get_mut(i))
+            .filter(|key| !key.revoked);
+        Ok(key.map(|key| key.revoked = true).is_some())
+    }
+}
+
+/// In-memory click store, for tests
+#[derive(Default)]
+pub struct MemoryClickStore {
+    clicks: Mutex<Vec<NewClick>>,
+}
+
+#[async_trait]
+impl ClickStore for MemoryClickStore {
+    async fn insert(&self, clicks: &[NewClick]) -> Result<()> {
+        self.clicks
+            .lock()
+            .unwrap()
+            .extend(clicks.iter().map(|click| NewClick {
+                clicked_at: click.clicked_at,
+                country: click.country.clone(),
+                referrer: click.referrer.clone(),
+                slug: click.slug.clone(),
+                user_agent: click.user_agent.clone(),
+                visitor: click.visitor.clone(),
+            }));
+        Ok(())
+    }
+
+    async fn stats(&self, slug: &str, query: &StatsQuery) -> Result<ClickStats> {
+        let clicks = self.clicks.lock().unwrap();
+        let clicks: Vec<&NewClick> = clicks
+            .iter()
+            .filter(|click| click.slug == slug && query.contains(click.clicked_at))
+            .collect();
+
+        // Visitors are counted once per bucket and overall, clicks without one are not counted
+        let unique = |clicks: &[&NewClick]| {
+            clicks
+                .iter()
+                .filter_map(|click| click.visitor.as_ref())
+                .collect::<HashSet<_>>()
+                .len() as i64
+        };
+
+        // Time series, in UTC like PostgreSQL `date_trunc`
+        let width = match query.bucket {
+            Bucket::Hour => TimeDelta::hours(1),
+            Bucket::Day => TimeDelta::days(1),
+        };
+        let mut buckets: BTreeMap<DateTime<Utc>, Vec<&NewClick>> = BTreeMap::new();
+        for click in &clicks {
+            let start = click.clicked_at.duration_trunc(width)?;
+            buckets.entry(start).or_default().push(click);
+        }
+        let series = buckets
+            .into_iter()
+            .map(|(start, clicks)| SeriesPoint {
+                clicks: clicks.len() as i64,
+                start,
+                unique_visitors: unique(&clicks),
+            })
+            .collect();
+
+        // Top referrers, ties by name
+        let mut referrers: HashMap<&str, i64> = HashMap::new();
+        for referrer in clicks.iter().filter_map(|click| click.referrer.as_deref()) {
+            *referrers.entry(referrer).or_default() += 1;
+        }
+        let mut top_referrers: Vec<ReferrerCount> = referrers
+            .into_iter()
+            .map(|(referrer, clicks)| ReferrerCount {
+                clicks,
+                referrer: referrer.to_string(),
+            })
+            .collect();
+        top_referrers.sort_by(|a, b| b.clicks.cmp(&a.clicks).then(a.referrer.cmp(&b.referrer)));
+        top_referrers.truncate(TOP_REFERRERS);
+
+        Ok(ClickStats {
+            series,
+            top_referrers,
+            total_clicks: clicks.len() as i64,
+            unique_visitors: unique(&clicks),
+        })
+    }
+}
+
+/// In-memory rate limiter, for tests, buckets are never evicted
+#[derive(Default)]
+pub struct MemoryRateLimiter {
+    /// Tokens left and last refill, by key
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn take(
+        &self,
+        key: &str,
+        tokens: u32,
+        capacity: u32,
+        refill_per_second: u32,
+    ) -> Result<Option<u64>> {
+        let (cost, capacity, rate) = (
+            f64::from(tokens),
+            f64::from(capacity),
+            f64::from(refill_per_second),
+        );
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let (tokens, refilled_at) = buckets.entry(key.to_string()).or_insert((capacity, now));
+
+        // Refill since the last request, like the Redis script
+        *tokens = capacity.min(*tokens + now.duration_since(*refilled_at).as_secs_f64() * rate);
+        *refilled_at = now;
+        if *tokens >= cost {
+            *tokens -= cost;
+            return Ok(None);
+        }
+        Ok(Some(((cost - *tokens) * 1000. / rate).ceil() as u64))
+    }
+}
+
+/// In-memory idempotency store, for tests, records are kept as JSON like in Redis
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    /// Record and expiration, by key
+    records: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl MemoryIdempotencyStore {
+    /// Keys held, claimed or completed
+    pub fn keys(&self) -> Vec<String> {
+        let now = Instant::now();
+        let records = self.records.lock().unwrap();
+        records
+            .iter()
+            .filter(|(_, (_, expires_at))| *expires_at > now)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let pending = serde_json::to_string(&IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+        })?;
+        let mut records = self.records.lock().unwrap();
+
+        // Expire like Redis would
+        let now = Instant::now();
+        records.retain(|_, (_, expires_at)| *expires_at > now);
+        if let Some((record, _)) = records.get(key) {
+            return Ok(Some(serde_json::from_str(record)?));
+        }
+        records.insert(
+            key.to_string(),
+            (pending, now + Duration::from_secs(ttl_seconds)),
+        );
+        Ok(None)
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()> {
+        let expires_at = Instant::now() + Duration::from_secs(ttl_seconds);
+        self.records.lock().unwrap().insert(
+            key.to_string(),
+            (serde_json::to_string(record)?, expires_at),
+        );
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        self.records.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use super::{
+    ApiKey, Bucket, ClickStats, ClickStore, KeyStore, Link, LinkFilter, LinkUpdate, NewClick,
+    NewLink, ReferrerCount, SeriesPoint, SlugStore, StatsQuery, TOP_REFERRERS,
+};
+use crate::slug::{RedirectType, first_char};
+use crate::telemetry::postgres_span;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{
+    Pool as PostgresPool,
+    tokio_postgres::{
+        Row,
+        binary_copy::BinaryCopyInWriter,
+        types::{ToSql, Type},
+    },
+};
+use futures_util::{StreamExt, stream::BoxStream};
+use std::collections::HashSet;
+use std::pin::pin;
+use tracing::Instrument;
+
+/// Columns selected to build a link
+const LINK_COLUMNS: &str = "slug, url, owner, redirect_type, created_at, expires_at";
+
+/// Slug store on the PostgreSQL `slugs` table, partitioned by first character
+pub struct PgSlugStore {
+    pg_pool: PostgresPool,
+}
+
+impl PgSlugStore {
+    pub fn new(pg_pool: PostgresPool) -> Self {
+        Self { pg_pool }
+    }
+}
+
+#[async_trait]
+impl SlugStore for PgSlugStore {
+    async fn insert(&self, link: &NewLink) -> Result<bool> {
+        let client = self.pg_pool.get().await?;
+        let rows = client
+            .execute("INSERT INTO slugs (first_char, slug, url, owner, expires_at, redirect_type) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING", &[&first_char(&link.slug), &link.slug, &link.url, &link.owner, &link.expires_at, &(u16::from(link.redirect_type) as i16)])
+            .instrument(postgres_span("INSERT", "slugs"))
+            .await?;
+        Ok(rows == 1)
+    }
+
+    async fn insert_many(&self, links: &[NewLink]) -> Result<Vec<bool>> {
+        let client = self.pg_pool.get().await?;
+        let slugs: Vec<&str> = links.iter().map(|link| link.slug.as_str()).collect();
+        let urls: Vec<&str> = links.iter().map(|link| link.url.as_str()).collect();
+        let owners: Vec<Option<&str>> = links.iter().map(|link| link.owner.as_deref()).collect();
+        let expires_at: Vec<_> = links.iter().map(|link| link.expires_at).collect();
+        let redirect_types: Vec<i16> = links
+            .iter()
+            .map(|link| u16::from(link.redirect_type) as i16)
+            .collect();
+        let rows = client
+            .query("INSERT INTO slugs (first_char, slug, url, owner, expires_at, redirect_type) SELECT left(slug, 1), slug, url, owner, expires_at, redirect_type FROM unnest($1::text[], $2::text[], $3::text[], $4::timestamptz[], $5::int2[]) AS t (slug, url, owner, expires_at, redirect_type) ON CONFLICT DO NOTHING RETURNING slug", &[&slugs, &urls, &owners, &expires_at, &redirect_types])
+            .instrument(postgres_span("INSERT", "slugs"))
+            .await?;
+
+        // Only the first occurrence of a repeated slug is inserted
+        let mut inserted: HashSet<String> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(links
+            .iter()
+            .map(|link| inserted.remove(&link.slug))
+            .collect())
+    }
+
+    async fn import(&self, links: &[NewLink]) -> Result<Vec<bool>> {
+        let mut client = self.pg_pool.get().await?;
+        let tx = client.transaction().await?;
+
+        // Copy to a staging table, COPY itself would abort on the first conflict
+        tx.batch_execute("CREATE TEMP TABLE slugs_import (slug TEXT, url TEXT, owner TEXT, expires_at TIMESTAMPTZ, redirect_type SMALLINT) ON COMMIT DROP")
+            .await?;
+        let sink = tx
+            .copy_in(
+                "COPY slugs_import (slug, url, owner, expires_at, redirect_type) FROM STDIN BINARY",
+            )
+            .instrument(postgres_span("COPY", "slugs_import"))
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(
+            sink,
+            &[
+                Type::TEXT,
+                Type::TEXT,
+                Type::TEXT,
+                Type::TIMESTAMPTZ,
+                Type::INT2
+            ],
+        ));
+        for link in links {
+            let redirect_type = u16::from(link.redirect_type) as i16;
+            writer
+                .as_mut()
+                .write(&[
+                    &link.slug,
+                    &link.url,
+                    &link.owner,
+                    &link.expires_at,
+                    &redirect_type,
+                ])
+                .await?;
+        }
+        writer.finish().await?;
+
+        // Move to the partitions, skipping taken slugs
+        let rows = tx
+            .query("INSERT INTO slugs (first_char, slug, url, owner, expires_at, redirect_type) SELECT left(slug, 1), slug, url, owner, expires_at, redirect_type FROM slugs_import ON CONFLICT DO NOTHING RETURNING slug", &[])
+            .instrument(postgres_span("INSERT", "slugs"))
+            .await?;
+        tx.commit().await?;
+
+        // Only the first occurrence of a repeated slug is inserted
+        let mut inserted: HashSet<String> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(links
+            .iter()
+            .map(|link| inserted.remove(&link.slug))
+            .collect())
+    }
+
+    async fn export(&self, filter: &LinkFilter) -> Result<BoxStream<'static, Result<Link>>> {
+        let client = self.pg_pool.get().await?;
+        let params: [&(dyn ToSql + Sync); 3] = [&filter.owner, &filter.from, &filter.to];
+        let rows = client
+            .query_raw(
+                &format!("SELECT {LINK_COLUMNS} FROM slugs WHERE ($1::text IS NULL OR owner = $1) AND ($2::timestamptz IS NULL OR created_at >= $2) AND ($3::timestamptz IS NULL OR created_at < $3) ORDER BY created_at, slug"),
+                params,
+            )
+            .instrument(postgres_span("SELECT", "slugs"))
+            .await?;
+
+        // Hold the connection until the stream ends
+        Ok(rows
+            .map(move |row| {
+                let _client = &client;
+                link_from_row(&row?)
+            })
+            .boxed())
+    }
+
+    async fn get(&self, slug: &str) -> Result<Option<Link>> {
+        let client = self.pg_pool.get().await?;
+        client
+            .query_opt(
+                &format!("SELECT {LINK_COLUMNS} FROM slugs WHERE first_char = $1 AND slug = $2"),
+                &[&first_char(slug), &slug],
+            )
+            .instrument(postgres_span("SELECT", "slugs"))
+            .await?
+            .as_ref()
+            .map(link_from_row)
+            .transpose()
+    }
+
+    async fn find_by_url(&self, url: &str, owner: Option<&str>) -> Result<Option<Link>> {
+        let client = self.pg_pool.get().await?;
+        client
+            .query_opt(
+                &format!("SELECT {LINK_COLUMNS} FROM slugs WHERE url = $1 AND owner IS NOT DISTINCT FROM $2 AND (expires_at IS NULL OR expires_at > now()) ORDER BY created_at, slug LIMIT 1"),
+                &[&url, &owner],
+            )
+            .instrument(postgres_span("SELECT", "slugs"))
+            .await?
+            .as_ref()
+            .map(link_from_row)
+            .transpose()
+    }
+
+    async fn update(&self, slug: &str, update: &LinkUpdate) -> Result<Option<Link>> {
+        let client = self.pg_pool.get().await?;
+        client
+            .query_opt(
+                &format!("UPDATE slugs SET url = COALESCE($3, url), redirect_type = COALESCE($4, redirect_type), expires_at = CASE WHEN $5 THEN $6 ELSE expires_at END WHERE first_char = $1 AND slug = $2 RETURNING {LINK_COLUMNS}"),
+                &[
+                    &first_char(slug),
+                    &slug,
+                    &update.url,
+                    &update.redirect_type.map(|t| u16::from(t) as i16),
+                    &update.expires_at.is_some(),
+                    &update.expires_at.flatten(),
+                ],
+            )
+            .instrument(postgres_span("UPDATE", "slugs"))
+            .await?
+            .as_ref()
+            .map(link_from_row)
+            .transpose()
+    }
+
+    async fn delete(&self, slug: &str) -> Result<bool> {
+        let client = self.pg_pool.get().await?;
+        let rows = client
+            .execute(
+                "DELETE FROM slugs WHERE first_char = $1 AND slug = $2",
+                &[&first_char(slug), &slug],
+            )
+            .instrument(postgres_span("DELETE", "slugs"))
+            .await?;
+        Ok(rows == 1)
+    }
+
+    async fn taken(&self, slugs: &[String]) -> Result<HashSet<String>> {
+        let client = self.pg_pool.get().await?;
+        let rows = client
+            .query("SELECT slug FROM slugs WHERE slug = ANY($1)", &[&slugs])
+            .instrument(postgres_span("SELECT", "slugs"))
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let client = self.pg_pool.get().await?;
+        client.execute("SELECT 1", &[]).await?;
+        Ok(())
+    }
+}
+
+/// API key store on the PostgreSQL `api_keys` table
+pub struct PgKeyStore {
+    pg_pool: PostgresPool,
+}
+
+impl PgKeyStore {
+    pub fn new(pg_pool: PostgresPool) -> Self {
+        Self { pg_pool }
+    }
+}
+
+#[async_trait]
+impl KeyStore for PgKeyStore {
+    async fn create(&self, owner: &str, key_hash: &[u8]) -> Result<ApiKey> {
+        let client = self.pg_pool.get().await?;
+        let row = client
+            .query_one(
+                "INSERT INTO api_keys (owner, key_hash) VALUES ($1, $2) RETURNING id, created_at",
+                &[&owner, &key_hash],
+            )
+            .instrument(postgres_span("INSERT", "api_keys"))
+            .await?;
+        Ok(ApiKey {
+            created_at: row.get(1),
+            id: row.get(0),
+            owner: owner.to_string(),
+        })
+    }
+
+    async fn owner(&self, key_hash: &[u8]) -> Result<Option<String>> {
+        let client = self.pg_pool.get().await?;
+        let row = client
+            .query_opt(
+                "SELECT owner FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+                &[&key_hash],
+            )
+            .instrument(postgres_span("SELECT", "api_keys"))
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn revoke(&self, id: i64) -> Result<bool> {
+        let client = self.pg_pool.get().await?;
+        let rows = client
+            .execute(
+                "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+                &[&id],
+            )
+            .instrument(postgres_span("UPDATE", "api_keys"))
+            .await?;
+        Ok(rows == 1)
+    }
+}
+
+/// Click store on the PostgreSQL `clicks` table, partitioned by first character like `slugs`
+pub struct PgClickStore {
+    pg_pool: PostgresPool,
+}
+
+impl PgClickStore {
+    pub fn new(pg_pool: PostgresPool) -> Self {
+        Self { pg_pool }
+    }
+}
+
+#[async_trait]
+impl ClickStore for PgClickStore {
+    async fn insert(&self, clicks: &[NewClick]) -> Result<()> {
+        let first_chars: Vec<&str> = clicks.iter().map(|c| first_char(&c.slug)).collect();
+        let slugs: Vec<&str> = clicks.iter().map(|c| c.slug.as_str()).collect();
+        let clicked_ats: Vec<DateTime<Utc>> = clicks.iter().map(|c| c.clicked_at).collect();
+        let referrers: Vec<Option<&str>> = clicks.iter().map(|c| c.referrer.as_deref()).collect();
+        let user_agents: Vec<Option<&str>> =
+            clicks.iter().map(|c| c.user_agent.as_deref()).collect();
+        let countries: Vec<Option<&str>> = clicks.iter().map(|c| c.country.as_deref()).collect();
+        let visitors: Vec<Option<&str>> = clicks.iter().map(|c| c.visitor.as_deref()).collect();
+
+        let client = self.pg_pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO clicks (first_char, slug, clicked_at, referrer, user_agent, country, visitor) SELECT * FROM UNNEST($1::text[], $2::text[], $3::timestamptz[], $4::text[], $5::text[], $6::text[], $7::text[])",
+                &[&first_chars, &slugs, &clicked_ats, &referrers, &user_agents, &countries, &visitors],
+            )
+            .instrument(postgres_span("INSERT", "clicks"))
+            .await?;
+        Ok(())
+    }
+
+    async fn stats(&self, slug: &str, query: &StatsQuery) -> Result<ClickStats> {
+        // All queries are scoped to the slug partition and the requested range
+        let client = self.pg_pool.get().await?;
+        let scope = "first_char = $1 AND slug = $2 AND ($3::timestamptz IS NULL OR clicked_at >= $3) AND ($4::timestamptz IS NULL OR clicked_at < $4)";
+        let params: [&(dyn ToSql + Sync); 4] = [&first_char(slug), &slug, &query.from, &query.to];
+
+        // Totals
+        let totals = client
+            .query_one(
+                &format!("SELECT count(*), count(DISTINCT visitor) FROM clicks WHERE {scope}"),
+                &params,
+            )
+            .instrument(postgres_span("SELECT", "clicks"))
+            .await?;
+
+        // Time series
+        let bucket = match query.bucket {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        };
+        let series = client
+            .query(
+                &format!("SELECT date_trunc($5, clicked_at, 'UTC') AS start, count(*), count(DISTINCT visitor) FROM clicks WHERE {scope} GROUP BY start ORDER BY start"),
+                &[params.as_slice(), &[&bucket]].concat(),
+            )
+            .instrument(postgres_span("SELECT", "clicks"))
+            .await?
+            .iter()
+            .map(|row| SeriesPoint {
+                clicks: row.get(1),
+                start: row.get(0),
+                unique_visitors: row.get(2),
+            })
+            .collect();
+
+        // Top referrers
+        let top_referrers = client
+            .query(
+                &format!("SELECT referrer, count(*) AS clicks FROM clicks WHERE {scope} AND referrer IS NOT NULL GROUP BY referrer ORDER BY clicks DESC, referrer LIMIT $5"),
+                &[params.as_slice(), &[&(TOP_REFERRERS as i64)]].concat(),
+            )
+            .instrument(postgres_span("SELECT", "clicks"))
+            .await?
+            .iter()
+            .map(|row| ReferrerCount {
+                clicks: row.get(1),
+                referrer: row.get(0),
+            })
+            .collect();
+
+        Ok(ClickStats {
+            series,
+            top_referrers,
+            total_clicks: totals.get(0),
+            unique_visitors: totals.get(1),
+        })
+    }
+}
+
+/// Build a link from a row selected with `LINK_COLUMNS`
+fn link_from_row(row: &Row) -> Result<Link> {
+    Ok(Link {
+        created_at: row.get(4),
+        expires_at: row.get(5),
+        owner: row.get(2),
+        redirect_type: RedirectType::try_from(row.get::<usize, i16>(3) as u16)
+            .map_err(anyhow::Error::msg)?,
+        slug: row.get(0),
+        url: row.get(1),
+    })
+}
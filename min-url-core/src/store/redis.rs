@@ -0,0 +1,348 @@
+use super::{IdempotencyRecord, IdempotencyStore, RateLimiter, SlugCache, SlugPool};
+use crate::slug::{INVALIDATION_CHANNEL, SLUG_POOL, SlugEntry, cache_key};
+use crate::telemetry::redis_span;
+use anyhow::Result;
+use async_trait::async_trait;
+use deadpool_redis::{
+    Pool as RedisPool,
+    redis::{Client as RedisClient, cmd, pipe},
+};
+use futures_util::{StreamExt, stream::BoxStream};
+use tracing::Instrument;
+
+/// Slug cache on Redis, entries are JSON `SlugEntry` values keyed by `cache_key`, changes are published on `INVALIDATION_CHANNEL`
+pub struct RedisSlugCache {
+    /// Dedicated pub/sub connections are opened from it
+    client: RedisClient,
+    redis_pool: RedisPool,
+}
+
+impl RedisSlugCache {
+    pub fn new(redis_pool: RedisPool, redis_url: &str) -> Result<Self> {
+        Ok(Self {
+            client: RedisClient::open(redis_url)?,
+            redis_pool,
+        })
+    }
+}
+
+#[async_trait]
+impl SlugCache for RedisSlugCache {
+    async fn get(&self, slug: &str) -> Result<Option<SlugEntry>> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("GET")
+            .arg(cache_key(slug))
+            .query_async::<Option<String>>(&mut redis_conn)
+            .instrument(redis_span("GET"))
+            .await?
+            .map(SlugEntry::from_redis)
+            .transpose()
+    }
+
+    async fn set(&self, slug: &str, entry: &SlugEntry, max_ttl_seconds: Option<u64>) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let mut set = cmd("SET");
+        set.arg(cache_key(slug)).arg(serde_json::to_string(entry)?);
+        let ttl = entry.ttl_seconds().map(|ttl| ttl as u64);
+        if let Some(ttl) = ttl.into_iter().chain(max_ttl_seconds).min() {
+            set.arg("EX").arg(ttl);
+        }
+        set.query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("SET"))
+            .await?;
+        Ok(())
+    }
+
+    async fn set_many(&self, entries: &[(String, SlugEntry)]) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let mut batch = pipe();
+        for (slug, entry) in entries {
+            let set = batch
+                .cmd("SET")
+                .arg(cache_key(slug))
+                .arg(serde_json::to_string(entry)?);
+            if let Some(ttl) = entry.ttl_seconds() {
+                set.arg("EX").arg(ttl);
+            }
+            set.ignore();
+        }
+        batch
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("SET"))
+            .await?;
+        Ok(())
+    }
+
+    async fn invalidate(&self, slug: &str) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        pipe()
+            .cmd("DEL")
+            .arg(cache_key(slug))
+            .cmd("PUBLISH")
+            .arg(INVALIDATION_CHANNEL)
+            .arg(slug)
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("DEL"))
+            .await?;
+        Ok(())
+    }
+
+    async fn publish(&self, slug: &str) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("PUBLISH")
+            .arg(INVALIDATION_CHANNEL)
+            .arg(slug)
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("PUBLISH"))
+            .await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<String>>> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(INVALIDATION_CHANNEL).await?;
+        Ok(pubsub
+            .into_on_message()
+            .map(|msg| Ok(msg.get_payload()?))
+            .boxed())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("PING").query_async::<()>(&mut redis_conn).await?;
+        Ok(())
+    }
+}
+
+/// Slug pool on the Redis `SLUG_POOL` list
+pub struct RedisSlugPool {
+    redis_pool: RedisPool,
+}
+
+impl RedisSlugPool {
+    pub fn new(redis_pool: RedisPool) -> Self {
+        Self { redis_pool }
+    }
+}
+
+#[async_trait]
+impl SlugPool for RedisSlugPool {
+    async fn pop(&self) -> Result<Option<String>> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let slug = cmd("RPOP")
+            .arg(SLUG_POOL)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("RPOP"))
+            .await?;
+        Ok(slug)
+    }
+
+    async fn pop_many(&self, count: usize) -> Result<Vec<String>> {
+        // Nil when the list is empty
+        let mut redis_conn = self.redis_pool.get().await?;
+        let slugs: Option<Vec<String>> = cmd("RPOP")
+            .arg(SLUG_POOL)
+            .arg(count)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("RPOP"))
+            .await?;
+        Ok(slugs.unwrap_or_default())
+    }
+
+    async fn push(&self, slugs: &[String]) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("RPUSH")
+            .arg(SLUG_POOL)
+            .arg(slugs)
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("RPUSH"))
+            .await?;
+        Ok(())
+    }
+
+    async fn size(&self) -> Result<usize> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let len = cmd("LLEN")
+            .arg(SLUG_POOL)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("LLEN"))
+            .await?;
+        Ok(len)
+    }
+
+    async fn peek(&self, count: usize) -> Result<Vec<String>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Slugs are popped from the tail
+        let mut redis_conn = self.redis_pool.get().await?;
+        let mut slugs: Vec<String> = cmd("LRANGE")
+            .arg(SLUG_POOL)
+            .arg(-(count as isize))
+            .arg(-1)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("LRANGE"))
+            .await?;
+        slugs.reverse();
+        Ok(slugs)
+    }
+
+    async fn clear(&self) -> Result<usize> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let (size, _): (usize, usize) = pipe()
+            .atomic()
+            .cmd("LLEN")
+            .arg(SLUG_POOL)
+            .cmd("DEL")
+            .arg(SLUG_POOL)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("DEL"))
+            .await?;
+        Ok(size)
+    }
+}
+
+/// Token bucket, shared across replicas and timed by the Redis clock
+///
+/// Takes the bucket key, the tokens to take, capacity and refill rate, returns whether the request is allowed and the milliseconds until enough tokens are available.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local cost = tonumber(ARGV[1])
+local capacity = tonumber(ARGV[2])
+local rate = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate / 1000)
+local allowed = 0
+local retry_ms = 0
+if tokens >= cost then
+    tokens = tokens - cost
+    allowed = 1
+else
+    retry_ms = math.ceil((cost - tokens) * 1000 / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * 1000 / rate))
+return {allowed, retry_ms}
+"#;
+
+/// Rate limiter on Redis hashes, one per bucket key
+pub struct RedisRateLimiter {
+    redis_pool: RedisPool,
+}
+
+impl RedisRateLimiter {
+    pub fn new(redis_pool: RedisPool) -> Self {
+        Self { redis_pool }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for RedisRateLimiter {
+    async fn take(
+        &self,
+        key: &str,
+        tokens: u32,
+        capacity: u32,
+        refill_per_second: u32,
+    ) -> Result<Option<u64>> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        let (allowed, retry_ms): (u8, u64) = cmd("EVAL")
+            .arg(TOKEN_BUCKET_SCRIPT)
+            .arg(1)
+            .arg(key)
+            .arg(tokens)
+            .arg(capacity)
+            .arg(refill_per_second)
+            .query_async(&mut redis_conn)
+            .instrument(redis_span("EVAL"))
+            .await?;
+        Ok((allowed == 0).then_some(retry_ms))
+    }
+}
+
+/// Prefix of idempotency keys in Redis, slug cache keys take `slug::SLUG_KEY_PREFIX` so slugs cannot collide with them
+const IDEMPOTENCY_KEY_PREFIX: &str = "idempotency:";
+
+/// Idempotency store on Redis, records are JSON `IdempotencyRecord` values keyed by `IDEMPOTENCY_KEY_PREFIX` and the key
+pub struct RedisIdempotencyStore {
+    redis_pool: RedisPool,
+}
+
+impl RedisIdempotencyStore {
+    pub fn new(redis_pool: RedisPool) -> Self {
+        Self { redis_pool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for RedisIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        ttl_seconds: u64,
+    ) -> Result<Option<IdempotencyRecord>> {
+        let key = format!("{IDEMPOTENCY_KEY_PREFIX}{key}");
+        let mut redis_conn = self.redis_pool.get().await?;
+        let pending = serde_json::to_string(&IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+        })?;
+        loop {
+            let claimed: Option<String> = cmd("SET")
+                .arg(&key)
+                .arg(&pending)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl_seconds)
+                .query_async(&mut redis_conn)
+                .instrument(redis_span("SET"))
+                .await?;
+            if claimed.is_some() {
+                return Ok(None);
+            }
+
+            // Taken, unless it expired or was released since, then claim it again
+            let record: Option<String> = cmd("GET")
+                .arg(&key)
+                .query_async(&mut redis_conn)
+                .instrument(redis_span("GET"))
+                .await?;
+            if let Some(record) = record {
+                return Ok(Some(serde_json::from_str(&record)?));
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        key: &str,
+        record: &IdempotencyRecord,
+        ttl_seconds: u64,
+    ) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("SET")
+            .arg(format!("{IDEMPOTENCY_KEY_PREFIX}{key}"))
+            .arg(serde_json::to_string(record)?)
+            .arg("EX")
+            .arg(ttl_seconds)
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("SET"))
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        let mut redis_conn = self.redis_pool.get().await?;
+        cmd("DEL")
+            .arg(format!("{IDEMPOTENCY_KEY_PREFIX}{key}"))
+            .query_async::<()>(&mut redis_conn)
+            .instrument(redis_span("DEL"))
+            .await?;
+        Ok(())
+    }
+}
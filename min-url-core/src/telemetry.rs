@@ -0,0 +1,151 @@
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use std::env;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Service telemetry, flushing pending spans on shutdown
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Initialize logging, exporting spans if a collector is configured
+    pub fn init(service: &'static str) -> Result<Self> {
+        let tracer_provider = init_tracer_provider(service)?;
+        let target = service.replace('-', "_");
+        tracing_subscriber::registry()
+            .with(
+                tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                    // Axum logs rejections from built-in extractors with the `axum::rejection` target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+                    format!(
+                        "{target}=debug,{}=debug,tower_http=debug,axum::rejection=trace",
+                        env!("CARGO_CRATE_NAME")
+                    )
+                    .into()
+                }),
+            )
+            .with(tracing_subscriber::fmt::layer())
+            .with(tracer_provider.as_ref().map(|provider| {
+                tracing_opentelemetry::layer().with_tracer(provider.tracer(target))
+            }))
+            .init();
+        Ok(Self { tracer_provider })
+    }
+
+    /// Flush pending spans
+    pub fn shutdown(self) -> Result<()> {
+        if let Some(provider) = self.tracer_provider {
+            provider.shutdown()?;
+        }
+        Ok(())
+    }
+}
+
+/// Build the OTLP span exporter if `OTEL_EXPORTER_OTLP_ENDPOINT` is set, and propagate W3C trace context
+fn init_tracer_provider(service: &'static str) -> Result<Option<SdkTracerProvider>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // If no collector is configured, only log
+    if env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+
+    // Export spans in batches, over OTLP/HTTP, the exporter reads its settings from `OTEL_EXPORTER_OTLP_*`
+    let exporter = SpanExporter::builder().with_http().build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service).build())
+        .build();
+    Ok(Some(provider))
+}
+
+/// Expose Prometheus metrics on a dedicated listener, with custom buckets for `_seconds` histograms
+pub fn init_metrics(addr: SocketAddr, latency_buckets: Option<&[f64]>) -> Result<()> {
+    let mut builder = PrometheusBuilder::new().with_http_listener(addr);
+    if let Some(buckets) = latency_buckets {
+        builder =
+            builder.set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), buckets)?;
+    }
+    builder.install()?;
+    Ok(())
+}
+
+/// Request span, continuing the W3C trace context of the caller
+pub fn make_request_span(req: &Request) -> Span {
+    let method = req.method();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str());
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{method} {route}"),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = route,
+        url.path = req.uri().path(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    if let Err(e) = span.set_parent(parent) {
+        tracing::debug!("Failed to continue trace context: {e:?}");
+    }
+    span
+}
+
+/// Record request latency per route
+pub async fn track_metrics(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let res = next.run(req).await;
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "route" => route,
+        "status" => res.status().as_u16().to_string(),
+    )
+    .record(start.elapsed().as_secs_f64());
+    res
+}
+
+/// Redis command span
+pub fn redis_span(command: &'static str) -> Span {
+    tracing::info_span!(
+        "redis",
+        otel.name = command,
+        otel.kind = "client",
+        db.system.name = "redis",
+        db.operation.name = command,
+    )
+}
+
+/// PostgreSQL query span
+pub fn postgres_span(operation: &'static str, collection: &'static str) -> Span {
+    tracing::info_span!(
+        "postgresql",
+        otel.name = format!("{operation} {collection}"),
+        otel.kind = "client",
+        db.system.name = "postgresql",
+        db.operation.name = operation,
+        db.collection.name = collection,
+    )
+}
@@ -0,0 +1,59 @@
+use crate::Backends;
+use anyhow::Result;
+use clap::Subcommand;
+use futures_util::TryStreamExt;
+use min_url_core::store::LinkFilter;
+
+/// Cache command
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Remove a slug from Redis and the redirect-svc memory caches
+    Purge { slug: String },
+    /// Cache every live link from PostgreSQL in Redis
+    Rebuild {
+        /// Links written per round trip
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+    },
+}
+
+/// Run a cache command
+pub async fn run(command: CacheCommand, backends: &Backends) -> Result<()> {
+    match command {
+        CacheCommand::Purge { slug } => {
+            backends.cache.invalidate(&slug).await?;
+            println!("Purged {slug}");
+        }
+        CacheCommand::Rebuild { batch_size } => {
+            let cached = rebuild(backends, batch_size.max(1)).await?;
+            println!("Cached {cached} links");
+        }
+    }
+    Ok(())
+}
+
+/// Copy live links to Redis in batches, streaming them from the store, returns the number of links cached
+async fn rebuild(backends: &Backends, batch_size: usize) -> Result<u64> {
+    let mut links = backends.store.export(&LinkFilter::default()).await?;
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut cached = 0;
+    while let Some(link) = links.try_next().await? {
+        // Expired links answer 410 from PostgreSQL
+        let entry = link.entry();
+        if entry.is_expired() {
+            continue;
+        }
+        batch.push((link.slug, entry));
+
+        // Write a full batch in a single round trip, expiring with the slugs
+        if batch.len() == batch_size {
+            backends.cache.set_many(&batch).await?;
+            cached += batch.drain(..).len() as u64;
+        }
+    }
+    if !batch.is_empty() {
+        backends.cache.set_many(&batch).await?;
+        cached += batch.len() as u64;
+    }
+    Ok(cached)
+}
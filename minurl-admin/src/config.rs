@@ -0,0 +1,24 @@
+use crate::Cli;
+use anyhow::Result;
+use min_url_core::config::{self, Settings};
+use serde::Deserialize;
+
+/// minurl-admin settings
+#[derive(Deserialize)]
+pub struct Config {
+    pub database_url: String,
+    pub redis_url: String,
+}
+
+impl Config {
+    /// Load from the configuration file, environment variables and CLI flags
+    pub fn load(cli: &Cli) -> Result<Self> {
+        config::load(&cli.args, cli)
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
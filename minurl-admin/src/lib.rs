@@ -0,0 +1,75 @@
+//! Operations CLI of min-url-rs: manages links, the slug pool and the Redis cache directly on the storage backends.
+
+mod cache;
+pub mod config;
+mod links;
+mod partitions;
+mod pool;
+
+use anyhow::Result;
+use cache::CacheCommand;
+use clap::{Parser, Subcommand};
+use deadpool_postgres::Pool as PostgresPool;
+use links::LinkCommand;
+use min_url_core::{
+    config::ConfigArgs,
+    store::{SlugCache, SlugPool, SlugStore},
+};
+use pool::PoolCommand;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Operate the URL shortener, directly on PostgreSQL and Redis
+///
+/// Settings are read from the configuration file, then environment variables (e.g. `DATABASE_URL`), then these flags.
+#[derive(Parser, Serialize)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    #[serde(skip)]
+    args: ConfigArgs,
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Command,
+    /// PostgreSQL URL
+    #[arg(long, global = true)]
+    database_url: Option<String>,
+    /// Redis URL
+    #[arg(long, global = true)]
+    redis_url: Option<String>,
+}
+
+/// Admin command
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create, look up, disable or delete links
+    #[command(subcommand)]
+    Link(LinkCommand),
+    /// Inspect or flush the slug pool
+    #[command(subcommand)]
+    Pool(PoolCommand),
+    /// Purge or rebuild the Redis cache
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Print the rows and size of each `slugs_*` partition
+    Partitions,
+}
+
+/// Storage backends, through the same stores as the services
+pub struct Backends {
+    pub cache: Arc<dyn SlugCache>,
+    /// PostgreSQL, for partition stats the stores do not cover, `None` without a database
+    pub pg_pool: Option<PostgresPool>,
+    pub pool: Arc<dyn SlugPool>,
+    pub store: Arc<dyn SlugStore>,
+}
+
+/// Run a command
+pub async fn run(command: Command, backends: &Backends) -> Result<()> {
+    match command {
+        Command::Link(command) => links::run(command, backends).await,
+        Command::Pool(command) => pool::run(command, backends).await,
+        Command::Cache(command) => cache::run(command, backends).await,
+        Command::Partitions => partitions::run(backends).await,
+    }
+}
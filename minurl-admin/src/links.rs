@@ -0,0 +1,177 @@
+use crate::Backends;
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use min_url_core::{
+    link::{
+        AllocationError, DEFAULT_ALLOCATION_RETRIES, allocate, check_custom_slug, is_http_url,
+        resolve_expiration,
+    },
+    slug::RedirectType,
+    store::{Link, LinkUpdate, NewLink},
+};
+use serde::Serialize;
+use url::Url;
+
+/// Link command
+#[derive(Subcommand)]
+pub enum LinkCommand {
+    /// Create a link, with a slug from the pool unless one is given
+    Create {
+        /// Target URL
+        url: Url,
+        /// Custom slug
+        #[arg(long)]
+        slug: Option<String>,
+        /// Owner, as in API keys
+        #[arg(long)]
+        owner: Option<String>,
+        /// Expiration date (RFC 3339)
+        #[arg(long, conflicts_with = "ttl_seconds")]
+        expires_at: Option<DateTime<Utc>>,
+        /// Seconds until expiration
+        #[arg(long)]
+        ttl_seconds: Option<u64>,
+        /// Redirect status code (301, 302, 303, 307 or 308)
+        #[arg(long, default_value = "303", value_parser = parse_redirect_type)]
+        redirect_type: RedirectType,
+    },
+    /// Print a link and whether it is cached in Redis
+    Lookup { slug: String },
+    /// Expire a link now, redirects answer 410 Gone
+    Disable { slug: String },
+    /// Delete a link
+    Delete { slug: String },
+}
+
+/// Looked up link JSON output
+#[derive(Serialize)]
+struct LookupOutput {
+    cached: bool,
+    #[serde(flatten)]
+    link: Link,
+}
+
+/// Run a link command
+pub async fn run(command: LinkCommand, backends: &Backends) -> Result<()> {
+    match command {
+        LinkCommand::Create {
+            url,
+            slug,
+            owner,
+            expires_at,
+            ttl_seconds,
+            redirect_type,
+        } => {
+            // Check the request like write-svc does
+            ensure!(is_http_url(&url), "URL must be HTTP(S)");
+            ensure!(
+                owner.as_ref().is_none_or(|owner| !owner.trim().is_empty()),
+                "owner must not be blank"
+            );
+            let expires_at = resolve_expiration(expires_at, ttl_seconds)?;
+
+            // Insert, with the given slug or one from the pool
+            let mut link = NewLink {
+                expires_at,
+                owner,
+                redirect_type,
+                slug: String::new(),
+                url: url.to_string(),
+            };
+            match slug {
+                Some(slug) => {
+                    check_custom_slug(&slug)?;
+                    link.slug = slug;
+                    ensure!(
+                        backends.store.insert(&link).await?,
+                        "slug {} is taken",
+                        link.slug
+                    );
+                }
+                None => {
+                    let allocated = allocate(
+                        backends.pool.as_ref(),
+                        backends.store.as_ref(),
+                        &mut link,
+                        DEFAULT_ALLOCATION_RETRIES,
+                    )
+                    .await;
+                    match allocated {
+                        Ok(()) => (),
+                        Err(AllocationError::NoSlug) => bail!("slug pool is empty"),
+                        Err(AllocationError::Conflict) => {
+                            bail!("no free slug after {DEFAULT_ALLOCATION_RETRIES} attempts")
+                        }
+                        Err(AllocationError::Unavailable(e)) => return Err(e),
+                    }
+                }
+            }
+
+            // Cache in Redis, and evict negative entries from memory caches
+            let created = find(backends, &link.slug).await?;
+            let cached = async {
+                backends
+                    .cache
+                    .set(&link.slug, &created.entry(), None)
+                    .await?;
+                backends.cache.publish(&link.slug).await
+            };
+            cached.await.with_context(|| {
+                format!("Created {}, but failed to cache it in Redis", link.slug)
+            })?;
+
+            println!("{}", serde_json::to_string_pretty(&created)?);
+        }
+        LinkCommand::Lookup { slug } => {
+            let link = find(backends, &slug).await?;
+            let cached = backends.cache.get(&slug).await?.is_some();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&LookupOutput { cached, link })?
+            );
+        }
+        LinkCommand::Disable { slug } => {
+            let update = LinkUpdate {
+                expires_at: Some(Some(Utc::now())),
+                ..LinkUpdate::default()
+            };
+            if backends.store.update(&slug, &update).await?.is_none() {
+                bail!("slug {slug} not found");
+            }
+            backends
+                .cache
+                .invalidate(&slug)
+                .await
+                .with_context(|| format!("Disabled {slug}, but failed to purge it from Redis"))?;
+            println!("Disabled {slug}");
+        }
+        LinkCommand::Delete { slug } => {
+            if !backends.store.delete(&slug).await? {
+                bail!("slug {slug} not found");
+            }
+            backends
+                .cache
+                .invalidate(&slug)
+                .await
+                .with_context(|| format!("Deleted {slug}, but failed to purge it from Redis"))?;
+            println!("Deleted {slug}");
+        }
+    }
+    Ok(())
+}
+
+/// Get a link, failing if unknown
+async fn find(backends: &Backends, slug: &str) -> Result<Link> {
+    backends
+        .store
+        .get(slug)
+        .await?
+        .with_context(|| format!("slug {slug} not found"))
+}
+
+/// Parse a redirect status code
+fn parse_redirect_type(value: &str) -> Result<RedirectType, String> {
+    let code: u16 = value.parse().map_err(|_| format!("invalid code {value}"))?;
+    RedirectType::try_from(code)
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use clap::Parser;
+use min_url_core::{
+    pools::Pools,
+    store::{PgSlugStore, RedisSlugCache, RedisSlugPool},
+};
+use minurl_admin::{Backends, Cli, config::Config};
+use std::sync::Arc;
+
+/// Entrypoint
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Load configuration
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+
+    // Connect Redis and PostgreSQL
+    let Pools {
+        pg_pool,
+        redis_pool,
+    } = Pools::connect(&config.database_url, &config.redis_url)?;
+    let backends = Backends {
+        cache: Arc::new(RedisSlugCache::new(redis_pool.clone(), &config.redis_url)?),
+        pool: Arc::new(RedisSlugPool::new(redis_pool)),
+        store: Arc::new(PgSlugStore::new(pg_pool.clone())),
+        pg_pool: Some(pg_pool),
+    };
+
+    // Run the command
+    minurl_admin::run(cli.command, &backends).await
+}
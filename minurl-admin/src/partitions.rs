@@ -0,0 +1,56 @@
+use crate::Backends;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+/// Print the rows and on-disk size of each `slugs_*` partition
+pub async fn run(backends: &Backends) -> Result<()> {
+    let pg_pool = backends
+        .pg_pool
+        .as_ref()
+        .context("partitions need PostgreSQL")?;
+    let client = pg_pool.get().await?;
+
+    // Exact row counts, per partition key
+    let counts: HashMap<String, i64> = client
+        .query(
+            "SELECT first_char, count(*) FROM slugs GROUP BY first_char",
+            &[],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    // Partitions, with their table and index size
+    let partitions = client
+        .query(
+            "SELECT c.relname, pg_size_pretty(pg_total_relation_size(c.oid)), pg_size_pretty(sum(pg_total_relation_size(c.oid)) OVER ()) FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid WHERE i.inhparent = 'slugs'::regclass ORDER BY c.relname",
+            &[],
+        )
+        .await?;
+
+    println!("{:<12} {:>12} {:>10}", "partition", "rows", "size");
+    for row in &partitions {
+        let name: &str = row.get(0);
+        let rows = name
+            .strip_prefix("slugs_")
+            .and_then(|first_char| counts.get(first_char))
+            .copied()
+            .unwrap_or_default();
+        println!(
+            "{:<12} {:>12} {:>10}",
+            name,
+            rows,
+            row.get::<usize, &str>(1)
+        );
+    }
+    if let Some(row) = partitions.first() {
+        println!(
+            "{:<12} {:>12} {:>10}",
+            "total",
+            counts.values().sum::<i64>(),
+            row.get::<usize, &str>(2)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use crate::Backends;
+use anyhow::Result;
+use clap::Subcommand;
+use min_url_core::slug::SLUG_POOL;
+
+/// Slug pool command
+#[derive(Subcommand)]
+pub enum PoolCommand {
+    /// Print the pool size and the next slugs to be allocated
+    Inspect {
+        /// Next slugs to print
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+    },
+    /// Empty the pool, slug-filler refills it with fresh slugs
+    Flush,
+}
+
+/// Run a slug pool command
+pub async fn run(command: PoolCommand, backends: &Backends) -> Result<()> {
+    match command {
+        PoolCommand::Inspect { count } => {
+            let size = backends.pool.size().await?;
+            println!("{SLUG_POOL}: {size} slugs");
+            if count == 0 || size == 0 {
+                return Ok(());
+            }
+            println!("Next:");
+            for slug in backends.pool.peek(count.min(size)).await? {
+                println!("  {slug}");
+            }
+        }
+        PoolCommand::Flush => {
+            let size = backends.pool.clear().await?;
+            println!("Flushed {size} slugs from {SLUG_POOL}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use min_url_core::store::{ClickStore, NewClick};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, Receiver, Sender, error::TrySendError};
+use tokio::time::{self, Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// Clicks buffered in memory before new ones get dropped
+const BUFFER_SIZE: usize = 10_000;
+
+/// Clicks written to the store in a single statement
+const BATCH_SIZE: usize = 500;
+
+/// Maximum delay before buffered clicks are written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Visitor ID key, HMAC-SHA-256 with the configured secret
+type VisitorKey = Hmac<Sha256>;
+
+/// Redirect followed by a client
+pub struct Click {
+    pub clicked_at: DateTime<Utc>,
+    /// Only kept in memory, hashed with the user agent into a visitor ID before writing
+    pub client_ip: Option<IpAddr>,
+    /// Always empty until a GeoIP source is wired
+    pub country: Option<String>,
+    pub referrer: Option<String>,
+    pub slug: String,
+    pub user_agent: Option<String>,
+}
+
+/// Click recorder, buffers clicks in memory and writes them in batches off the request path
+#[derive(Clone)]
+pub struct ClickRecorder {
+    stop: CancellationToken,
+    tx: Sender<Click>,
+}
+
+impl ClickRecorder {
+    /// Create the recorder and spawn its background writer on the tracker, visitor IDs are keyed with the secret
+    pub fn spawn(store: Arc<dyn ClickStore>, visitor_id_secret: &str, tasks: &TaskTracker) -> Self {
+        let (tx, rx) = mpsc::channel(BUFFER_SIZE);
+        let stop = CancellationToken::new();
+        let visitor_key = VisitorKey::new_from_slice(visitor_id_secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        tasks.spawn(run(rx, store, visitor_key, stop.clone()));
+        Self { stop, tx }
+    }
+
+    /// Stop recording, the writer exits once buffered clicks are written
+    pub fn close(&self) {
+        self.stop.cancel();
+    }
+
+    /// Record a click without waiting, dropping it if the buffer is full
+    pub fn record(&self, click: Click) {
+        if let Err(TrySendError::Full(click)) = self.tx.try_send(click) {
+            tracing::warn!("Click buffer full, dropped click on {}", click.slug);
+        }
+    }
+}
+
+/// Background writer, flushes when a batch is full or the interval elapses
+async fn run(
+    mut rx: Receiver<Click>,
+    store: Arc<dyn ClickStore>,
+    visitor_key: VisitorKey,
+    stop: CancellationToken,
+) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut interval = time::interval(FLUSH_INTERVAL);
+    loop {
+        let limit = BATCH_SIZE - batch.len();
+        tokio::select! {
+            received = rx.recv_many(&mut batch, limit) => {
+                // If the channel is closed and drained, write what is left and stop
+                if received == 0 {
+                    flush(store.as_ref(), &visitor_key, &mut batch).await;
+                    return;
+                }
+                if batch.len() >= BATCH_SIZE {
+                    flush(store.as_ref(), &visitor_key, &mut batch).await;
+                }
+            }
+            _ = interval.tick() => flush(store.as_ref(), &visitor_key, &mut batch).await,
+            // On shutdown, refuse new clicks and keep draining the buffered ones
+            _ = stop.cancelled(), if !rx.is_closed() => rx.close(),
+        }
+    }
+}
+
+/// Write the batch to the store, clicks are dropped on failure
+async fn flush(store: &dyn ClickStore, visitor_key: &VisitorKey, batch: &mut Vec<Click>) {
+    if batch.is_empty() {
+        return;
+    }
+    let clicks: Vec<NewClick> = batch
+        .drain(..)
+        .map(|click| NewClick {
+            visitor: visitor_id(visitor_key, &click),
+            clicked_at: click.clicked_at,
+            country: click.country,
+            referrer: click.referrer,
+            slug: click.slug,
+            user_agent: click.user_agent,
+        })
+        .collect();
+    match store.insert(&clicks).await {
+        Ok(()) => tracing::debug!("Recorded {} clicks", clicks.len()),
+        Err(e) => tracing::error!("Failed to record {} clicks: {e:?}", clicks.len()),
+    }
+}
+
+/// Pseudonymous visitor ID, an HMAC of the client IP and user agent, which cannot be brute-forced without the secret
+fn visitor_id(visitor_key: &VisitorKey, click: &Click) -> Option<String> {
+    let ip = click.client_ip?;
+    let digest = visitor_key
+        .clone()
+        .chain_update(ip.to_string())
+        .chain_update(b"|")
+        .chain_update(click.user_agent.as_deref().unwrap_or_default())
+        .finalize()
+        .into_bytes();
+    Some(format!("{digest:x}")[..32].to_string())
+}
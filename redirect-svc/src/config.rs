@@ -0,0 +1,195 @@
+use anyhow::{Result, ensure};
+use clap::Parser;
+use min_url_core::{
+    config::{
+        self, ConfigArgs, Settings, default_bind_addr, default_metrics_addr,
+        default_shutdown_timeout_seconds,
+    },
+    net::TrustedProxies,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+/// Shortest visitor ID secret, for it not to be guessed
+const MIN_VISITOR_ID_SECRET_LEN: usize = 16;
+
+/// redirect-svc settings
+#[derive(Deserialize)]
+pub struct Config {
+    /// Public listener
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: SocketAddr,
+    /// Slugs kept in the per-instance memory cache
+    #[serde(default = "default_cache_size")]
+    pub cache_size: u64,
+    /// Seconds a slug stays in the memory cache, bounding staleness if an invalidation is missed
+    #[serde(default = "default_cache_ttl_seconds")]
+    pub cache_ttl_seconds: u64,
+    pub database_url: String,
+    #[serde(default = "default_metrics_addr")]
+    pub metrics_addr: SocketAddr,
+    /// Bytes of rendered QR codes kept in memory
+    #[serde(default = "default_qr_cache_bytes")]
+    pub qr_cache_bytes: u64,
+    /// Directory of PNG logos QR codes can be overlaid with, named after their file stem
+    #[serde(default)]
+    pub qr_logos_dir: Option<PathBuf>,
+    /// Seconds clients and CDNs may cache QR codes
+    #[serde(default = "default_qr_max_age_seconds")]
+    pub qr_max_age_seconds: u64,
+    /// QR codes rendered at once, others are rejected with 503
+    #[serde(default = "default_qr_render_concurrency")]
+    pub qr_render_concurrency: usize,
+    /// Seconds a slug read from PostgreSQL stays in Redis, bounding staleness if a change raced the read
+    #[serde(default = "default_redis_cache_ttl_seconds")]
+    pub redis_cache_ttl_seconds: u64,
+    pub redis_url: String,
+    /// Public base URL, encoded in QR codes
+    pub self_domain: Url,
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+    /// Reverse proxies whose `X-Forwarded-For` is trusted to identify visitors
+    #[serde(default)]
+    pub trusted_proxies: TrustedProxies,
+    /// Secret keying visitor IDs, for stored IDs not to be traced back to client addresses
+    pub visitor_id_secret: String,
+}
+
+impl Config {
+    /// Load from the configuration file, environment variables and CLI flags
+    pub fn load() -> Result<Self> {
+        let cli = Cli::parse();
+        config::load(&cli.args, &cli)
+    }
+
+    /// Memory cache entry lifetime
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl_seconds)
+    }
+
+    /// Shutdown drain deadline
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<()> {
+        ensure!(self.cache_size > 0, "cache_size must be positive");
+        ensure!(
+            self.cache_ttl_seconds > 0,
+            "cache_ttl_seconds must be positive"
+        );
+        ensure!(
+            matches!(self.self_domain.scheme(), "http" | "https"),
+            "self_domain must be an HTTP(S) URL"
+        );
+        ensure!(self.qr_cache_bytes > 0, "qr_cache_bytes must be positive");
+        ensure!(
+            self.qr_render_concurrency > 0,
+            "qr_render_concurrency must be positive"
+        );
+        ensure!(
+            self.redis_cache_ttl_seconds > 0,
+            "redis_cache_ttl_seconds must be positive"
+        );
+        ensure!(
+            self.qr_logos_dir.as_ref().is_none_or(|dir| dir.is_dir()),
+            "qr_logos_dir must be a directory"
+        );
+        ensure!(
+            self.visitor_id_secret.len() >= MIN_VISITOR_ID_SECRET_LEN,
+            "visitor_id_secret must be at least {MIN_VISITOR_ID_SECRET_LEN} characters"
+        );
+        Ok(())
+    }
+}
+
+/// Redirect slugs to their URL and render QR codes
+///
+/// Settings are read from the configuration file, then environment variables (e.g. `CACHE_SIZE`), then these flags.
+#[derive(Parser, Serialize)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    #[serde(skip)]
+    args: ConfigArgs,
+    /// Public listener [default: 0.0.0.0:8080]
+    #[arg(long)]
+    bind_addr: Option<SocketAddr>,
+    /// Slugs kept in the memory cache [default: 100]
+    #[arg(long)]
+    cache_size: Option<u64>,
+    /// Seconds a slug stays in the memory cache [default: 30]
+    #[arg(long)]
+    cache_ttl_seconds: Option<u64>,
+    /// PostgreSQL URL
+    #[arg(long)]
+    database_url: Option<String>,
+    /// Prometheus metrics listener [default: 0.0.0.0:9090]
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Bytes of rendered QR codes kept in memory [default: 33554432]
+    #[arg(long)]
+    qr_cache_bytes: Option<u64>,
+    /// Directory of PNG logos for QR codes
+    #[arg(long)]
+    qr_logos_dir: Option<PathBuf>,
+    /// Seconds clients and CDNs may cache QR codes [default: 86400]
+    #[arg(long)]
+    qr_max_age_seconds: Option<u64>,
+    /// QR codes rendered at once [default: 4]
+    #[arg(long)]
+    qr_render_concurrency: Option<usize>,
+    /// Seconds a slug read from PostgreSQL stays in Redis [default: 3600]
+    #[arg(long)]
+    redis_cache_ttl_seconds: Option<u64>,
+    /// Redis URL
+    #[arg(long)]
+    redis_url: Option<String>,
+    /// Public base URL
+    #[arg(long)]
+    self_domain: Option<Url>,
+    /// Seconds to drain requests, then pending writes, on shutdown [default: 10]
+    #[arg(long)]
+    shutdown_timeout_seconds: Option<u64>,
+    /// Reverse proxies trusted to report client addresses in `X-Forwarded-For`, comma-separated addresses or CIDR networks [default: none]
+    #[arg(long)]
+    trusted_proxies: Option<String>,
+    /// Secret keying visitor IDs, at least 16 characters
+    #[arg(long)]
+    visitor_id_secret: Option<String>,
+}
+
+/// Default memory cache capacity
+fn default_cache_size() -> u64 {
+    100
+}
+
+/// Default memory cache TTL
+fn default_cache_ttl_seconds() -> u64 {
+    30
+}
+
+/// Default QR code cache capacity, 32 MiB
+fn default_qr_cache_bytes() -> u64 {
+    32 * 1024 * 1024
+}
+
+/// Default QR code max age, a day
+fn default_qr_max_age_seconds() -> u64 {
+    86_400
+}
+
+/// Default QR codes rendered at once
+fn default_qr_render_concurrency() -> usize {
+    4
+}
+
+/// Default Redis read-through TTL, an hour
+fn default_redis_cache_ttl_seconds() -> u64 {
+    3600
+}
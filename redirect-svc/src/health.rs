@@ -0,0 +1,15 @@
+use crate::AppState;
+use axum::{Json, extract::State, response::IntoResponse};
+use futures_util::FutureExt;
+use min_url_core::health::Readiness;
+use std::sync::Arc;
+
+/// Readiness handler, returns 503 if PostgreSQL or Redis is unreachable
+pub async fn handle_ready_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let readiness = Readiness::check([
+        ("postgres", state.store.ping().boxed()),
+        ("redis", state.cache.ping().boxed()),
+    ])
+    .await;
+    (readiness.code(), Json(readiness))
+}
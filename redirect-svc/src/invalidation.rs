@@ -0,0 +1,35 @@
+use crate::AppState;
+use anyhow::Result;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+
+/// Evict slugs from the memory cache as they are published, reconnecting forever
+pub async fn subscribe(state: Arc<AppState>) {
+    loop {
+        if let Err(e) = listen(&state).await {
+            tracing::warn!("Invalidation subscription lost: {e:?}");
+        }
+
+        // Messages may have been missed while disconnected, start over from a clean cache
+        state.memory_cache.invalidate_all();
+        time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Listen to the invalidation channel until the connection drops
+async fn listen(state: &AppState) -> Result<()> {
+    // Subscribe on a dedicated connection
+    let mut slugs = state.cache.subscribe().await?;
+    tracing::debug!("Subscribed to slug invalidations");
+
+    // Evict each published slug
+    while let Some(slug) = slugs.next().await {
+        let slug = slug?;
+        state.memory_cache.invalidate(&slug).await;
+        tracing::debug!("Evicted slug {slug} from memory cache");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,391 @@
+//! Read path of min-url-rs: redirects slugs to their URL and renders QR codes.
+
+pub mod clicks;
+mod health;
+pub mod invalidation;
+pub mod qr;
+
+use anyhow::Result;
+use axum::Extension;
+use axum::http::StatusCode;
+use axum::{
+    Router,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, header},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::Utc;
+use clicks::{Click, ClickRecorder};
+use min_url_core::{
+    health::handle_health_get,
+    net::TrustedProxies,
+    slug::SlugEntry,
+    store::{SlugCache, SlugStore},
+    telemetry::{make_request_span, track_metrics},
+};
+use moka::future::Cache;
+use qr::{ImageFormat, Logo, QrImage, QrKey, QrStyle};
+use qrcode::types::QrError;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_util::task::TaskTracker;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::CompressionLayer, decompression::RequestDecompressionLayer, trace::TraceLayer,
+};
+use url::Url;
+
+/// Response header reporting the QR code version, its size in modules being `17 + 4 * version`
+const QR_VERSION_HEADER: header::HeaderName = header::HeaderName::from_static("x-qr-version");
+
+/// Web application state
+pub struct AppState {
+    /// Redis cache shared with write-svc
+    pub cache: Arc<dyn SlugCache>,
+    pub clicks: ClickRecorder,
+    /// Per-instance cache, `None` entries remember unknown slugs
+    pub memory_cache: Cache<String, Arc<Option<SlugEntry>>>,
+    /// Rendered QR codes, weighed by size in bytes
+    pub qr_cache: Cache<QrKey, Arc<QrImage>>,
+    /// Logos QR codes can be overlaid with, by name
+    pub qr_logos: HashMap<String, Arc<Logo>>,
+    /// Seconds clients and CDNs may cache QR codes, at most until the slug expires
+    pub qr_max_age_seconds: u64,
+    /// Permits to render a QR code on the blocking pool, bounding CPU taken from redirects
+    pub qr_renders: Arc<Semaphore>,
+    /// Seconds a slug read from PostgreSQL stays in Redis, bounding staleness if a change raced the read
+    pub redis_cache_ttl_seconds: u64,
+    /// Public base URL, encoded in QR codes
+    pub self_domain: Url,
+    pub store: Arc<dyn SlugStore>,
+    /// Fire & forget tasks, awaited on shutdown
+    pub tasks: TaskTracker,
+    /// Proxies reporting visitor addresses, for click statistics
+    pub trusted_proxies: TrustedProxies,
+}
+
+/// Build the web application, with its middlewares
+pub fn app(state: Arc<AppState>) -> Router {
+    // Register the slug handler, `_` is too short to be a slug and reserves the `/_/` prefix
+    Router::new()
+        .route("/{slug}", get(handle_redirect_get)) // Redirect to the URL
+        .route("/_/health", get(handle_health_get)) // Liveness probe
+        .route("/_/ready", get(health::handle_ready_get)) // Readiness probe
+        .layer(CompressionLayer::new())
+        // QR codes, registered after compression for their strong `ETag` to match the body sent
+        .route("/{slug}/qr", get(handle_qrcode_get)) // Generate QR code
+        .with_state(state)
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+                .layer(middleware::from_fn(track_metrics))
+                .layer(RequestDecompressionLayer::new()),
+        )
+}
+
+/// Handle QR code generation
+async fn handle_qrcode_get(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // Validate size
+    let size = match params.get("size") {
+        Some(size) => match size.parse::<u32>() {
+            Ok(size) => size.clamp(32, 512),
+            Err(_) => 128, // Default to 128
+        },
+        None => 128, // Default to 128
+    };
+
+    // Validate format, if unknown, return 400
+    let format = match params.get("format") {
+        Some(format) => match ImageFormat::from_str(format.as_str()) {
+            Ok(format) => format,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        None => ImageFormat::Svg, // Default to SVG
+    };
+
+    // Validate style
+    let style = match QrStyle::from_params(&params, &state.qr_logos) {
+        Ok(style) => style,
+        Err(status) => return status.into_response(),
+    };
+
+    // Get the slug from the cache or live databases
+    match lookup_cached(&slug, &state).await {
+        // If slug expired, return 410
+        Ok(Some(entry)) if entry.is_expired() => StatusCode::GONE.into_response(),
+        // If slug found, generate QR code, or reuse it
+        Ok(Some(entry)) => {
+            let qr_code = generate_qrcode(&slug, format, size, style, &state).await;
+            match qr_code {
+                Ok(Some(qr_code)) => {
+                    tracing::debug!(
+                        "Generated QR code: slug={}, size={}, format={:?}",
+                        slug,
+                        size,
+                        format
+                    );
+                    qrcode_res(&qr_code, &entry, &headers, &state)
+                }
+                // If all renderers are busy, return 503, redirects come first
+                Ok(None) => {
+                    tracing::debug!("QR code renderers busy: slug={}", slug);
+                    metrics::counter!("qr_renders_rejected_total").increment(1);
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        [(header::RETRY_AFTER, "1")],
+                    )
+                        .into_response()
+                }
+                // If the URL does not fit, return 422, a lower error correction level may help
+                Err(e) if e.downcast_ref::<QrError>() == Some(&QrError::DataTooLong) => {
+                    tracing::debug!("URL too long for QR code: slug={}", slug);
+                    StatusCode::UNPROCESSABLE_ENTITY.into_response()
+                }
+                Err(e) => {
+                    tracing::error!("Failed to generate QR code: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        // If slug not found, return 404
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        // If there was an error, return 503
+        Err(e) => {
+            tracing::error!("Failed to lookup slug: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+/// Handle HTTP redirects
+async fn handle_redirect_get(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    match lookup_cached(&slug, &state).await {
+        // If slug expired, return 410
+        Ok(Some(entry)) if entry.is_expired() => StatusCode::GONE.into_response(),
+        // If slug found, record the click and redirect to it
+        Ok(Some(entry)) => {
+            let header_str = |name| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            // Use the client address reported by trusted proxies, if any
+            let client_ip = state
+                .trusted_proxies
+                .client_ip(peer.map(|Extension(ConnectInfo(addr))| addr), &headers);
+            state.clicks.record(Click {
+                clicked_at: Utc::now(),
+                client_ip,
+                country: None,
+                referrer: header_str(header::REFERER),
+                slug,
+                user_agent: header_str(header::USER_AGENT),
+            });
+            (
+                entry.redirect_type.status(),
+                [(header::LOCATION, entry.url)],
+            )
+                .into_response()
+        }
+        // If slug not found, return 404
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        // If there was an error, return 503
+        Err(e) => {
+            tracing::error!("Failed to lookup slug: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+/// Get a slug entry from the memory cache or live databases if required, expired entries are returned as-is
+async fn lookup_cached(slug: &str, state: &AppState) -> Result<Option<SlugEntry>> {
+    // Check in memory cache
+    let cached = state.memory_cache.get(slug).await;
+    record_lookup("memory", cached.is_some());
+    if let Some(entry) = cached {
+        // If the entry is None, return 404
+        if entry.is_none() {
+            tracing::debug!("Slug {slug} cached as None");
+            return Ok(None);
+        }
+        // Otherwise, return it
+        let entry = entry.as_ref().clone().unwrap();
+        tracing::debug!("Slug {} cached as {}", slug, &entry.url);
+        return Ok(Some(entry));
+    }
+
+    // Check live
+    match lookup_live(slug, state).await {
+        // If slug found, cache and return it
+        Ok(Some(entry)) => {
+            // Store in memory cache
+            state
+                .memory_cache
+                .insert(slug.to_string(), Arc::new(Some(entry.clone())))
+                .await;
+            Ok(Some(entry))
+        }
+        // If slug is not found, cache and return 404
+        Ok(None) => {
+            // Store in memory cache
+            state
+                .memory_cache
+                .insert(slug.to_string(), Arc::new(None))
+                .await;
+            Ok(None)
+        }
+        // If there was an error, return it
+        Err(e) => Err(e),
+    }
+}
+
+/// Get a slug entry from the databases (PostgreSQL and Redis)
+#[tracing::instrument(skip(state))]
+async fn lookup_live(slug: &str, state: &AppState) -> Result<Option<SlugEntry>> {
+    // If slug is in Redis, return it
+    let cached = state.cache.get(slug).await?;
+    record_lookup("redis", cached.is_some());
+    if let Some(entry) = cached {
+        tracing::debug!("Slug {slug} found in Redis");
+        return Ok(Some(entry));
+    }
+
+    // Look up the slug in PostgreSQL
+    let link = state.store.get(slug).await?;
+
+    // If not found, return None
+    record_lookup("postgres", link.is_some());
+    let Some(link) = link else {
+        tracing::debug!("Slug {slug} not found");
+        return Ok(None);
+    };
+    let entry = link.entry();
+
+    // If expired, return it without caching in Redis
+    if entry.is_expired() {
+        tracing::debug!("Slug {slug} expired");
+        return Ok(Some(entry));
+    }
+
+    // Store it in Redis, expiring with the slug or sooner as a change may have happened since the read (fire & forget), and return it
+    let cache = state.cache.clone();
+    let slug = slug.to_string();
+    let cached = entry.clone();
+    let max_ttl_seconds = state.redis_cache_ttl_seconds;
+    state.tasks.spawn(async move {
+        match cache.set(&slug, &cached, Some(max_ttl_seconds)).await {
+            Ok(()) => tracing::debug!("Stored slug {slug} in Redis"),
+            Err(e) => tracing::warn!("Failed to store slug {slug} in Redis: {}", e),
+        }
+    });
+    Ok(Some(entry))
+}
+
+/// Count a lookup hit or miss on a storage tier
+fn record_lookup(tier: &'static str, hit: bool) {
+    metrics::counter!(
+        "slug_lookups_total",
+        "tier" => tier,
+        "result" => if hit { "hit" } else { "miss" },
+    )
+    .increment(1);
+}
+
+/// Generate a QR code for the given URL, use the public URL as QR content, cached per slug and parameters
+///
+/// Renders on the blocking pool, returns `None` without waiting if all permits are taken.
+async fn generate_qrcode(
+    slug: &str,
+    format: ImageFormat,
+    size: u32,
+    style: QrStyle,
+    state: &AppState,
+) -> Result<Option<Arc<QrImage>>> {
+    // Check in QR code cache
+    let key = QrKey::new(slug, format, size, &style);
+    let cached = state.qr_cache.get(&key).await;
+    metrics::counter!(
+        "qr_cache_lookups_total",
+        "result" => if cached.is_some() { "hit" } else { "miss" },
+    )
+    .increment(1);
+    if let Some(image) = cached {
+        return Ok(Some(image));
+    }
+
+    // Take a render permit, held until rendering ends even if the client goes away
+    let Ok(permit) = state.qr_renders.clone().try_acquire_owned() else {
+        return Ok(None);
+    };
+
+    // Build the public URL
+    let mut url = state.self_domain.clone();
+    url.set_path(slug);
+
+    // Generate and encode the QR code off the async runtime, then cache it
+    let image = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        qr::render(url.as_str(), format, size, &style)
+    })
+    .await??;
+    let image = Arc::new(image);
+    state.qr_cache.insert(key, image.clone()).await;
+    Ok(Some(image))
+}
+
+/// QR code response, 304 if the client already has it, cacheable until the slug expires
+fn qrcode_res(
+    image: &QrImage,
+    entry: &SlugEntry,
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Response {
+    let max_age = entry.ttl_seconds().map_or(state.qr_max_age_seconds, |ttl| {
+        state.qr_max_age_seconds.min(ttl as u64)
+    });
+    let cache_headers = [
+        (header::ETAG, image.etag.clone()),
+        (header::CACHE_CONTROL, format!("public, max-age={max_age}")),
+    ];
+
+    // If the client has the same image, skip the body, weak comparison as for any `If-None-Match`
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || opaque(tag) == opaque(&image.etag))
+        });
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (
+        cache_headers,
+        [
+            (header::CONTENT_TYPE, image.content_type.to_string()),
+            (QR_VERSION_HEADER, image.version.to_string()),
+        ],
+        image.body.clone(),
+    )
+        .into_response()
+}
@@ -0,0 +1,496 @@
+use anyhow::{Result, bail};
+use axum::{body::Bytes, http::StatusCode};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use image::{
+    DynamicImage, ImageFormat as ImageOutputFormat, Pixel, Rgb, RgbImage, RgbaImage,
+    imageops::{self, FilterType},
+};
+use qrcode::{Color, EcLevel, QrCode, Version};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use strum_macros::EnumString;
+
+/// Default quiet zone, in modules, as recommended by the QR specification
+const DEFAULT_MARGIN: u32 = 4;
+
+/// Largest quiet zone, in modules
+const MAX_MARGIN: u32 = 16;
+
+/// Logos span at most this fraction of the code width, few enough modules for `EcLevel::H` to recover
+const LOGO_SCALE: u32 = 5;
+
+/// Image format for QR code
+#[derive(Clone, Copy, Debug, EnumString, Eq, Hash, PartialEq)]
+pub enum ImageFormat {
+    #[strum(ascii_case_insensitive)]
+    Eps,
+    #[strum(ascii_case_insensitive)]
+    Gif,
+    #[strum(ascii_case_insensitive)]
+    Jpeg,
+    #[strum(ascii_case_insensitive)]
+    Pdf,
+    #[strum(ascii_case_insensitive)]
+    Png,
+    #[strum(ascii_case_insensitive)]
+    Svg,
+    /// UTF-8 half blocks, two rows of modules per line, size and logo ignored
+    #[strum(ascii_case_insensitive, serialize = "text", serialize = "txt")]
+    Text,
+    #[strum(ascii_case_insensitive)]
+    Webp,
+}
+
+/// Logo overlaid at the center of QR codes, loaded at startup
+pub struct Logo {
+    image: RgbaImage,
+    /// As requested in query parameters
+    name: String,
+    /// Source file, embedded as is in SVGs
+    png: Vec<u8>,
+}
+
+impl Logo {
+    /// Decode a PNG logo
+    pub fn from_png(name: &str, png: Vec<u8>) -> Result<Self> {
+        let image = image::load_from_memory_with_format(&png, ImageOutputFormat::Png)?.to_rgba8();
+        Ok(Self {
+            image,
+            name: name.to_string(),
+            png,
+        })
+    }
+}
+
+/// Load the `*.png` logos of a directory, named after their file stem
+pub fn load_logos(dir: &Path) -> Result<HashMap<String, Arc<Logo>>> {
+    let mut logos = HashMap::new();
+    for file in std::fs::read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().is_none_or(|ext| ext != "png") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            bail!("logo file name must be UTF-8: {}", path.display());
+        };
+        let logo = Logo::from_png(name, std::fs::read(&path)?)
+            .map_err(|e| anyhow::anyhow!("invalid logo {}: {e}", path.display()))?;
+        logos.insert(name.to_string(), Arc::new(logo));
+    }
+    Ok(logos)
+}
+
+/// QR code style, from query parameters
+#[derive(Clone)]
+pub struct QrStyle {
+    /// Dark modules color
+    pub dark: Rgb<u8>,
+    /// Raised to `EcLevel::H` with a logo
+    pub ec_level: EcLevel,
+    /// Light modules and quiet zone color
+    pub light: Rgb<u8>,
+    pub logo: Option<Arc<Logo>>,
+    /// Quiet zone, in modules
+    pub margin: u32,
+}
+
+impl QrStyle {
+    /// Parse `fg`, `bg` (`RRGGBB` hex colors, `#` optional), `margin`, `ec` (`L`, `M`, `Q` or `H`) and `logo`, 400 if invalid
+    pub fn from_params(
+        params: &HashMap<String, String>,
+        logos: &HashMap<String, Arc<Logo>>,
+    ) -> Result<Self, StatusCode> {
+        let dark = match params.get("fg") {
+            Some(fg) => parse_color(fg).ok_or(StatusCode::BAD_REQUEST)?,
+            None => Rgb([0, 0, 0]),
+        };
+        let light = match params.get("bg") {
+            Some(bg) => parse_color(bg).ok_or(StatusCode::BAD_REQUEST)?,
+            None => Rgb([255, 255, 255]),
+        };
+        let margin = match params.get("margin") {
+            Some(margin) => margin
+                .parse()
+                .ok()
+                .filter(|margin| *margin <= MAX_MARGIN)
+                .ok_or(StatusCode::BAD_REQUEST)?,
+            None => DEFAULT_MARGIN,
+        };
+        let logo = match params.get("logo") {
+            Some(name) => Some(logos.get(name).ok_or(StatusCode::BAD_REQUEST)?.clone()),
+            None => None,
+        };
+
+        // A logo hides modules, only the highest level recovers them reliably
+        let ec_level = match (&logo, params.get("ec")) {
+            (Some(_), _) => EcLevel::H,
+            (None, Some(ec)) => parse_ec_level(ec).ok_or(StatusCode::BAD_REQUEST)?,
+            (None, None) => EcLevel::L,
+        };
+
+        Ok(Self {
+            dark,
+            ec_level,
+            light,
+            logo,
+            margin,
+        })
+    }
+}
+
+/// Rendered QR codes cache key, any parameter changing the image is part of it
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub struct QrKey {
+    dark: Rgb<u8>,
+    /// `EcLevel` is not hashable
+    ec_level: u8,
+    format: ImageFormat,
+    light: Rgb<u8>,
+    logo: Option<String>,
+    margin: u32,
+    size: u32,
+    slug: String,
+}
+
+impl QrKey {
+    pub fn new(slug: &str, format: ImageFormat, size: u32, style: &QrStyle) -> Self {
+        Self {
+            dark: style.dark,
+            ec_level: style.ec_level as u8,
+            format,
+            light: style.light,
+            logo: style.logo.as_ref().map(|logo| logo.name.clone()),
+            margin: style.margin,
+            size,
+            slug: slug.to_string(),
+        }
+    }
+}
+
+/// Rendered QR code
+pub struct QrImage {
+    pub body: Bytes,
+    pub content_type: &'static str,
+    /// Weak entity tag, derived from the body, which may be served compressed
+    pub etag: String,
+    /// Smallest version fitting the content at the error correction level, 1 to 40
+    pub version: i16,
+}
+
+/// Render a QR code of the content, at least `size` pixels wide
+///
+/// Fails with `QrError::DataTooLong` if the content does not fit the largest version.
+pub fn render(content: &str, format: ImageFormat, size: u32, style: &QrStyle) -> Result<QrImage> {
+    let code = QrCode::with_error_correction_level(content.as_bytes(), style.ec_level)?;
+    let version = match code.version() {
+        Version::Normal(version) | Version::Micro(version) => version,
+    };
+    let matrix = Matrix::new(&code, style.margin, size);
+
+    // Encode
+    let (content_type, body) = match format {
+        ImageFormat::Eps => ("application/postscript", matrix.eps(style).into_bytes()),
+        ImageFormat::Pdf => ("application/pdf", matrix.pdf(style)),
+        ImageFormat::Svg => ("image/svg+xml", matrix.svg(style).into_bytes()),
+        ImageFormat::Text => ("text/plain; charset=utf-8", matrix.text().into_bytes()),
+        ImageFormat::Gif => ("image/gif", matrix.encode(style, ImageOutputFormat::Gif)?),
+        ImageFormat::Jpeg => ("image/jpeg", matrix.encode(style, ImageOutputFormat::Jpeg)?),
+        ImageFormat::Png => ("image/png", matrix.encode(style, ImageOutputFormat::Png)?),
+        ImageFormat::Webp => ("image/webp", matrix.encode(style, ImageOutputFormat::WebP)?),
+    };
+    let digest = Sha256::digest(&body);
+    let etag = format!(
+        "\"{}\"",
+        digest[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+
+    Ok(QrImage {
+        body: Bytes::from(body),
+        content_type,
+        etag,
+        version,
+    })
+}
+
+/// QR code modules laid out with their quiet zone
+struct Matrix {
+    /// Quiet zone, in modules
+    margin: u32,
+    /// Dark modules, row by row
+    modules: Vec<bool>,
+    /// Pixels per module
+    module_size: u32,
+    /// Modules per side, quiet zone included
+    width: u32,
+}
+
+impl Matrix {
+    /// Lay out the code, scaling modules for the image to be at least `size` pixels wide
+    fn new(code: &QrCode, margin: u32, size: u32) -> Self {
+        let code_width = code.width() as u32;
+        let width = code_width + 2 * margin;
+        let mut modules = vec![false; (width * width) as usize];
+        for (i, color) in code.to_colors().into_iter().enumerate() {
+            let (x, y) = (
+                i as u32 % code_width + margin,
+                i as u32 / code_width + margin,
+            );
+            modules[(y * width + x) as usize] = color == Color::Dark;
+        }
+        Self {
+            margin,
+            modules,
+            module_size: size.div_ceil(width).max(1),
+            width,
+        }
+    }
+
+    /// Whether the module is dark
+    fn is_dark(&self, x: u32, y: u32) -> bool {
+        self.modules[(y * self.width + x) as usize]
+    }
+
+    /// Image side, in pixels
+    fn pixels(&self) -> u32 {
+        self.width * self.module_size
+    }
+
+    /// Logo box, centered, as pixel offset and side, `None` without logo
+    fn logo_box(&self, style: &QrStyle) -> Option<(u32, u32)> {
+        style.logo.as_ref()?;
+        let side = (self.width - 2 * self.margin) * self.module_size / LOGO_SCALE;
+        Some(((self.pixels() - side) / 2, side))
+    }
+
+    /// Render as a bitmap, encoded in the given format
+    fn encode(&self, style: &QrStyle, format: ImageOutputFormat) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        DynamicImage::ImageRgb8(self.raster(style)).write_to(&mut Cursor::new(&mut buf), format)?;
+        Ok(buf)
+    }
+
+    /// Logo fitted on a light backing, keeping its aspect ratio, with its pixel offset, `None` without logo
+    fn logo_tile(&self, style: &QrStyle) -> Option<(u32, RgbImage)> {
+        let (logo, (offset, side)) = (style.logo.as_ref()?, self.logo_box(style)?);
+        let mut backing = RgbaImage::from_pixel(side, side, style.light.to_rgba());
+        let (width, height) = logo.image.dimensions();
+        let longest = width.max(height);
+        let fitted = imageops::resize(
+            &logo.image,
+            (width * side / longest).max(1),
+            (height * side / longest).max(1),
+            FilterType::Triangle,
+        );
+        imageops::overlay(
+            &mut backing,
+            &fitted,
+            i64::from((side - fitted.width()) / 2),
+            i64::from((side - fitted.height()) / 2),
+        );
+        Some((offset, DynamicImage::ImageRgba8(backing).to_rgb8()))
+    }
+
+    /// Render as a bitmap
+    fn raster(&self, style: &QrStyle) -> RgbImage {
+        let mut img = RgbImage::from_fn(self.pixels(), self.pixels(), |x, y| {
+            if self.is_dark(x / self.module_size, y / self.module_size) {
+                style.dark
+            } else {
+                style.light
+            }
+        });
+        if let Some((offset, tile)) = self.logo_tile(style) {
+            imageops::overlay(&mut img, &tile, i64::from(offset), i64::from(offset));
+        }
+        img
+    }
+
+    /// Render as SVG, one path for all dark modules
+    fn svg(&self, style: &QrStyle) -> String {
+        let pixels = self.pixels();
+        let mut svg = format!(
+            r#"<?xml version="1.0" standalone="yes"?><svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{pixels}" height="{pixels}" viewBox="0 0 {pixels} {pixels}" shape-rendering="crispEdges"><rect width="{pixels}" height="{pixels}" fill="{}"/><path fill="{}" d=""#,
+            hex_color(style.light),
+            hex_color(style.dark),
+        );
+        let size = self.module_size;
+        for y in 0..self.width {
+            for x in 0..self.width {
+                if self.is_dark(x, y) {
+                    let _ = write!(svg, "M{} {}h{size}v{size}h-{size}z", x * size, y * size);
+                }
+            }
+        }
+        svg.push_str(r#""/>"#);
+
+        // Embed the logo on a light backing
+        if let (Some(logo), Some((offset, side))) = (&style.logo, self.logo_box(style)) {
+            let _ = write!(
+                svg,
+                r#"<rect x="{offset}" y="{offset}" width="{side}" height="{side}" fill="{}"/><image x="{offset}" y="{offset}" width="{side}" height="{side}" href="data:image/png;base64,{}"/>"#,
+                hex_color(style.light),
+                BASE64_STANDARD.encode(&logo.png),
+            );
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+
+    /// Drawing operators shared by EPS and PDF, one point per pixel, origin at the bottom left
+    ///
+    /// `fill` sets the color from `r g b` components, `rect` fills an `x y w h` rectangle.
+    fn vector_ops(&self, style: &QrStyle, fill: &str, rect: &str) -> String {
+        let pixels = self.pixels();
+        let mut ops = format!(
+            "{} {fill}\n0 0 {pixels} {pixels} {rect}\n{} {fill}\n",
+            unit_color(style.light),
+            unit_color(style.dark),
+        );
+        let size = self.module_size;
+        for y in 0..self.width {
+            let bottom = (self.width - 1 - y) * size;
+            for x in 0..self.width {
+                if self.is_dark(x, y) {
+                    let _ = writeln!(ops, "{} {bottom} {size} {size} {rect}", x * size);
+                }
+            }
+        }
+        ops
+    }
+
+    /// Render as Encapsulated PostScript, the logo as an RGB image
+    fn eps(&self, style: &QrStyle) -> String {
+        let pixels = self.pixels();
+        let mut eps = format!(
+            "%!PS-Adobe-3.0 EPSF-3.0\n%%BoundingBox: 0 0 {pixels} {pixels}\n%%EndComments\n{}",
+            self.vector_ops(style, "setrgbcolor", "rectfill"),
+        );
+        if let Some((offset, tile)) = self.logo_tile(style) {
+            let (side, bottom) = (tile.width(), pixels - offset - tile.height());
+            let _ = write!(
+                eps,
+                "gsave\n{offset} {bottom} translate\n{side} {side} scale\n{side} {side} 8 [{side} 0 0 -{side} 0 {side}]\n<{}>\nfalse 3 colorimage\ngrestore\n",
+                tile.as_raw()
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>(),
+            );
+        }
+        eps.push_str("showpage\n%%EOF\n");
+        eps
+    }
+
+    /// Render as a single page PDF, the logo as an image XObject
+    fn pdf(&self, style: &QrStyle) -> Vec<u8> {
+        let pixels = self.pixels();
+        let mut content = self.vector_ops(style, "rg", "re f");
+        let mut resources = String::new();
+        let mut objects: Vec<Vec<u8>> = Vec::new();
+        if let Some((offset, tile)) = self.logo_tile(style) {
+            let (side, bottom) = (tile.width(), pixels - offset - tile.height());
+            let _ = writeln!(
+                content,
+                "q {side} 0 0 {side} {offset} {bottom} cm /Logo Do Q"
+            );
+            resources = " /Resources << /XObject << /Logo 5 0 R >> >>".to_string();
+            let mut image = format!(
+                "<< /Type /XObject /Subtype /Image /Width {side} /Height {side} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Length {} >>\nstream\n",
+                tile.as_raw().len(),
+            )
+            .into_bytes();
+            image.extend_from_slice(tile.as_raw());
+            image.extend_from_slice(b"\nendstream");
+            objects.push(image);
+        }
+        objects.splice(
+            0..0,
+            [
+                b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+                b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+                format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {pixels} {pixels}] /Contents 4 0 R{resources} >>").into_bytes(),
+                format!("<< /Length {} >>\nstream\n{content}endstream", content.len()).into_bytes(),
+            ],
+        );
+
+        // Number objects from 1, the cross-reference table points at each
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            pdf.extend_from_slice(object);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(trailer, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            trailer,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1,
+        );
+        pdf.extend_from_slice(trailer.as_bytes());
+        pdf
+    }
+
+    /// Render as UTF-8 half blocks, dark modules drawn, for light backgrounds
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for y in (0..self.width).step_by(2) {
+            for x in 0..self.width {
+                // The last line has no bottom row if the width is odd
+                let bottom = y + 1 < self.width && self.is_dark(x, y + 1);
+                text.push(match (self.is_dark(x, y), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Parse a `RRGGBB` hex color, `#` optional
+fn parse_color(value: &str) -> Option<Rgb<u8>> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// Format a color as `#rrggbb`
+fn hex_color(Rgb([r, g, b]): Rgb<u8>) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Format a color as PostScript and PDF `r g b` components, from 0 to 1
+fn unit_color(Rgb([r, g, b]): Rgb<u8>) -> String {
+    let unit = |c: u8| f32::from(c) / 255.;
+    format!("{:.3} {:.3} {:.3}", unit(r), unit(g), unit(b))
+}
+
+/// Parse an error correction level, from lowest to highest redundancy
+fn parse_ec_level(value: &str) -> Option<EcLevel> {
+    match value.to_ascii_uppercase().as_str() {
+        "L" => Some(EcLevel::L),
+        "M" => Some(EcLevel::M),
+        "Q" => Some(EcLevel::Q),
+        "H" => Some(EcLevel::H),
+        _ => None,
+    }
+}
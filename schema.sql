@@ -24,12 +24,49 @@ CREATE TABLE IF NOT EXISTS slugs (
     url        TEXT        NOT NULL,
     owner      TEXT,
     created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ,
+    redirect_type SMALLINT NOT NULL DEFAULT 303,
     CONSTRAINT slugs_pk PRIMARY KEY (first_char, slug)
 ) PARTITION BY LIST (first_char);
 
+-- Columns added since, for databases created before them
+ALTER TABLE slugs ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+-- Existing links keep the 303 See Other they were redirected with
+ALTER TABLE slugs ADD COLUMN IF NOT EXISTS redirect_type SMALLINT NOT NULL DEFAULT 303;
+
 -- Index on created_at for TTL/analytics
 CREATE INDEX IF NOT EXISTS slugs_created_at_idx ON slugs(created_at);
 
+-- Hash index on url for deduplication, equality lookups only
+CREATE INDEX IF NOT EXISTS slugs_url_idx ON slugs USING hash (url);
+
+------------------------------------------------------------
+-- API keys, only the SHA-256 of each key is stored
+------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS api_keys (
+    id         BIGINT      GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    owner      TEXT        NOT NULL,
+    key_hash   BYTEA       NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+
+------------------------------------------------------------
+-- Clicks table, partitioned like slugs so per-slug stats hit a single partition
+------------------------------------------------------------
+CREATE TABLE IF NOT EXISTS clicks (
+    slug       VARCHAR(256) NOT NULL,
+    first_char VARCHAR(1)   NOT NULL,
+    clicked_at TIMESTAMPTZ  NOT NULL,
+    referrer   TEXT,
+    user_agent TEXT,
+    country    VARCHAR(2),
+    visitor    VARCHAR(32)
+) PARTITION BY LIST (first_char);
+
+-- Index on slug and clicked_at for per-slug time series
+CREATE INDEX IF NOT EXISTS clicks_slug_clicked_at_idx ON clicks(slug, clicked_at);
+
 ------------------------------------------------------------
 -- Create partitions & attach triggers
 ------------------------------------------------------------
@@ -51,6 +88,10 @@ BEGIN
             'CREATE TRIGGER "trg_set_first_char_%1$s" BEFORE INSERT OR UPDATE ON "slugs_%1$s" FOR EACH ROW EXECUTE FUNCTION slugs_firstchar_trigger();',
             ch
         );
+        EXECUTE format(
+            'CREATE TABLE IF NOT EXISTS "clicks_%1$s" PARTITION OF clicks FOR VALUES IN (''%1$s'');',
+            ch
+        );
     END LOOP;
 END;
 $$ LANGUAGE plpgsql;
@@ -0,0 +1,114 @@
+use anyhow::{Result, ensure};
+use clap::Parser;
+use min_url_core::{
+    config::{self, ConfigArgs, Settings, default_metrics_addr},
+    slug::MAX_SLUG_LEN,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// slug-filler settings
+#[derive(Deserialize)]
+pub struct Config {
+    /// Slugs generated per refill, 10% of the pool size if unset
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    pub database_url: String,
+    #[serde(default = "default_metrics_addr")]
+    pub metrics_addr: SocketAddr,
+    /// Target `slug_pool` length
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    pub redis_url: String,
+    /// Milliseconds between refills
+    #[serde(default = "default_refill_interval_ms")]
+    pub refill_interval_ms: u64,
+    /// Generated slug length
+    #[serde(default = "default_slug_len")]
+    pub slug_len: usize,
+}
+
+impl Config {
+    /// Load from the configuration file, environment variables and CLI flags
+    pub fn load() -> Result<Self> {
+        let cli = Cli::parse();
+        config::load(&cli.args, &cli)
+    }
+
+    /// Slugs generated per refill
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.unwrap_or(self.queue_size / 10)
+    }
+
+    /// Delay between refills
+    pub fn refill_interval(&self) -> Duration {
+        Duration::from_millis(self.refill_interval_ms)
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<()> {
+        ensure!(self.queue_size > 0, "queue_size must be positive");
+        ensure!(
+            (1..=self.queue_size).contains(&self.batch_size()),
+            "batch_size must be between 1 and queue_size (defaults to 10% of queue_size)"
+        );
+        ensure!(
+            self.refill_interval_ms > 0,
+            "refill_interval_ms must be positive"
+        );
+        ensure!(
+            (1..=MAX_SLUG_LEN).contains(&self.slug_len),
+            "slug_len must be between 1 and {MAX_SLUG_LEN}"
+        );
+        Ok(())
+    }
+}
+
+/// Keep the Redis slug pool filled with unused random slugs
+///
+/// Settings are read from the configuration file, then environment variables (e.g. `QUEUE_SIZE`), then these flags.
+#[derive(Parser, Serialize)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    #[serde(skip)]
+    args: ConfigArgs,
+    /// Slugs generated per refill [default: 10% of queue size]
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// PostgreSQL URL
+    #[arg(long)]
+    database_url: Option<String>,
+    /// Prometheus metrics listener [default: 0.0.0.0:9090]
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Target slug pool length [default: 100000]
+    #[arg(long)]
+    queue_size: Option<usize>,
+    /// Redis URL
+    #[arg(long)]
+    redis_url: Option<String>,
+    /// Milliseconds between refills [default: 250]
+    #[arg(long)]
+    refill_interval_ms: Option<u64>,
+    /// Generated slug length [default: 6]
+    #[arg(long)]
+    slug_len: Option<usize>,
+}
+
+/// Default pool size
+fn default_queue_size() -> usize {
+    100_000
+}
+
+/// Default refill interval
+fn default_refill_interval_ms() -> u64 {
+    250
+}
+
+/// Default slug length
+fn default_slug_len() -> usize {
+    6
+}
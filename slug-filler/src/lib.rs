@@ -0,0 +1,61 @@
+//! Slug pool filler of min-url-rs: keeps the pool of free mini-slugs topped up.
+
+use anyhow::Result;
+use min_url_core::store::{SlugPool, SlugStore};
+use rand::{Rng, distr::Uniform};
+
+/// Base62 character set
+pub const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Slug filler, fills the slug pool with random slugs, ensuring that they are unique
+#[tracing::instrument(skip(pool, store, rng, dist))]
+pub async fn refill<R: Rng + ?Sized>(
+    pool: &dyn SlugPool,
+    store: &dyn SlugStore,
+    rng: &mut R,
+    dist: &Uniform<usize>,
+    queue_size: usize,
+    slug_len: usize,
+    batch_size: usize,
+) -> Result<()> {
+    // If the pool is already large enough, do nothing
+    let len = pool.size().await?;
+    metrics::gauge!("slug_pool_size").set(len as f64);
+    if len >= queue_size {
+        tracing::debug!("Current slug_pool size is {len}, no need to refill");
+        return Ok(());
+    }
+
+    // Generate a random batch
+    let mut batch: Vec<String> = Vec::with_capacity(batch_size);
+    for _ in 0..batch_size {
+        let slug: String = (0..slug_len)
+            .map(|_| BASE62[rng.sample(dist)] as char)
+            .collect();
+        batch.push(slug);
+    }
+
+    // Validate against the database
+    let taken = store.taken(&batch).await?;
+
+    // Remove existing slugs from the batch
+    if !taken.is_empty() {
+        batch.retain(|s| !taken.contains(s));
+        metrics::counter!("slug_collisions_total").increment(taken.len() as u64);
+        tracing::debug!("Removed {} existing slugs from the batch", taken.len());
+    }
+
+    // If the batch is empty, do nothing
+    if batch.is_empty() {
+        tracing::debug!("No new slugs to add to the slug_pool");
+        return Ok(());
+    }
+
+    // Push the batch to the pool
+    pool.push(&batch).await?;
+    metrics::counter!("slugs_pushed_total").increment(batch.len() as u64);
+    metrics::gauge!("slug_pool_size").set((len + batch.len()) as f64);
+    tracing::debug!("Added {} slugs to the slug_pool", batch.len());
+
+    Ok(())
+}
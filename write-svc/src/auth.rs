@@ -0,0 +1,204 @@
+use crate::AppState;
+use axum::{
+    Json,
+    extract::{FromRequestParts, Path, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Prefix of generated API keys, to make them recognizable in leaks and logs
+const KEY_PREFIX: &str = "mu_";
+
+/// Random characters in generated API keys (~238 bits of entropy)
+const KEY_LEN: usize = 40;
+
+/// Authenticated caller, from the `Authorization: Bearer` header
+#[derive(Clone)]
+pub enum Caller {
+    /// No credentials
+    Anonymous,
+    /// API key, owning the links it creates
+    Owner(String),
+    /// Admin token, managing keys and any link
+    Admin,
+}
+
+impl Caller {
+    /// Owner to attribute created links to, rejecting a client-supplied owner that is not the caller
+    pub fn resolve_owner(&self, claimed: Option<String>) -> Result<Option<String>, StatusCode> {
+        match (self, claimed) {
+            // Admins create links on behalf of anyone
+            (Self::Admin, claimed) => Ok(claimed),
+            (Self::Owner(owner), None) => Ok(Some(owner.clone())),
+            (Self::Owner(owner), Some(claimed)) if claimed == *owner => Ok(Some(claimed)),
+            (Self::Anonymous, None) => Ok(None),
+            (Self::Anonymous, Some(_)) => Err(StatusCode::UNAUTHORIZED),
+            (Self::Owner(_), Some(_)) => Err(StatusCode::FORBIDDEN),
+        }
+    }
+
+    /// Check the caller may manage a resource of the given owner (401 if anonymous, 403 if not theirs)
+    pub fn check_owner(&self, owner: Option<&str>) -> Result<(), StatusCode> {
+        match self {
+            Self::Admin => Ok(()),
+            Self::Owner(caller) if owner == Some(caller) => Ok(()),
+            Self::Owner(_) => Err(StatusCode::FORBIDDEN),
+            Self::Anonymous => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    /// Check the caller is an admin (401 if anonymous, 403 otherwise)
+    fn check_admin(&self) -> Result<(), StatusCode> {
+        match self {
+            Self::Admin => Ok(()),
+            Self::Owner(_) => Err(StatusCode::FORBIDDEN),
+            Self::Anonymous => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    /// Authenticate the caller from the `Authorization` header, looking up API keys (401 if invalid)
+    pub async fn authenticate(headers: &HeaderMap, state: &AppState) -> Result<Self, StatusCode> {
+        // If no credentials, the caller is anonymous
+        let Some(value) = headers.get(header::AUTHORIZATION) else {
+            return Ok(Self::Anonymous);
+        };
+
+        // Parse the bearer token
+        let token = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        let token_hash = hash_key(token);
+
+        // Check the admin token, comparing hashes avoids leaking it through timing
+        if state
+            .admin_token_hash
+            .as_ref()
+            .is_some_and(|admin_hash| *admin_hash == token_hash)
+        {
+            return Ok(Self::Admin);
+        }
+
+        // Look up the API key
+        let owner = state
+            .keys
+            .owner(&token_hash)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to lookup API key: {}", e);
+                StatusCode::SERVICE_UNAVAILABLE
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        Ok(Self::Owner(owner))
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for Caller {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        // If the rate limiter authenticated the caller already, reuse it
+        if let Some(caller) = parts.extensions.get::<Self>() {
+            return Ok(caller.clone());
+        }
+        Self::authenticate(&parts.headers, state).await
+    }
+}
+
+/// Create API key JSON payload
+#[derive(Deserialize)]
+pub struct CreateKeyPayload {
+    owner: String,
+}
+
+/// API key JSON payload, the key itself is only returned once, at creation
+#[derive(Serialize)]
+struct KeyPayload {
+    created_at: DateTime<Utc>,
+    id: i64,
+    key: String,
+    owner: String,
+}
+
+/// Create API key handler
+pub async fn handle_key_post(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(payload): Json<CreateKeyPayload>,
+) -> Result<impl IntoResponse, StatusCode> {
+    caller.check_admin()?;
+
+    // Check if owner is not blank
+    if payload.owner.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Generate the key, only its hash is stored
+    let key = format!(
+        "{KEY_PREFIX}{}",
+        rand::rng()
+            .sample_iter(Alphanumeric)
+            .take(KEY_LEN)
+            .map(char::from)
+            .collect::<String>()
+    );
+
+    // Store its hash
+    let created = state
+        .keys
+        .create(&payload.owner, &hash_key(&key))
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to insert API key: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?;
+    tracing::info!("Created API key {} for {}", created.id, created.owner);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(KeyPayload {
+            created_at: created.created_at,
+            id: created.id,
+            key,
+            owner: created.owner,
+        }),
+    ))
+}
+
+/// Revoke API key handler
+pub async fn handle_key_delete(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, StatusCode> {
+    caller.check_admin()?;
+
+    // Revoke, keeping the key for audit
+    let revoked = state.keys.revoke(id).await.map_err(|e| {
+        tracing::error!("Failed to revoke API key: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    // If nothing was revoked, the key is unknown or already revoked
+    if !revoked {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    tracing::info!("Revoked API key {id}");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Hash an API key or admin token, as stored
+pub fn hash_key(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
@@ -0,0 +1,156 @@
+use crate::{
+    AppState, ShortenPayload,
+    auth::Caller,
+    cache_slug, dedupe_payload, new_link,
+    rate_limit::{self, RateLimitKey},
+    validate_payload,
+};
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Links shortened per request
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// Batch item result JSON payload, the created link if the status is 201, or the existing one if 200
+#[derive(Serialize)]
+struct BatchResult {
+    #[serde(flatten)]
+    link: Option<ShortenPayload>,
+    status: u16,
+}
+
+/// Batch shorten URL handler, returns a result per payload, in order
+///
+/// Mini-slugs are popped from the pool together and links inserted in a single statement. Rate limited clients are charged a token per payload.
+pub async fn handle_shorten_batch_post(
+    State(state): State<Arc<AppState>>,
+    rate_limit_key: Option<Extension<RateLimitKey>>,
+    caller: Caller,
+    Json(mut payloads): Json<Vec<ShortenPayload>>,
+) -> Result<impl IntoResponse, Response> {
+    if payloads.len() > MAX_BATCH_SIZE {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+
+    // Charge the payloads beyond the first, already charged by the middleware
+    if let Some(Extension(key)) = rate_limit_key {
+        // A full bucket could never cover the batch
+        if payloads.len() > state.rate_limit.capacity as usize {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+        }
+        let extra = payloads.len().saturating_sub(1) as u32;
+        if extra > 0 {
+            rate_limit::charge(&state, &key, extra).await?;
+        }
+    }
+
+    // Validate payloads, custom slugs are inserted as is and others deduplicated or wait for a mini-slug
+    let mut statuses = vec![StatusCode::CREATED; payloads.len()];
+    let mut inserting = Vec::new();
+    let mut waiting = Vec::new();
+    for (i, payload) in payloads.iter_mut().enumerate() {
+        if let Err(status) = validate_payload(payload, &caller) {
+            statuses[i] = status;
+            continue;
+        }
+        if payload.dedupe && payload.slug.is_none() {
+            match dedupe_payload(&state, payload).await {
+                Ok(true) => {
+                    statuses[i] = StatusCode::OK;
+                    continue;
+                }
+                Ok(false) => (),
+                Err(status) => {
+                    statuses[i] = status;
+                    continue;
+                }
+            }
+        }
+        match payload.slug.take() {
+            Some(custom) => inserting.push((i, custom, true)),
+            None => waiting.push(i),
+        }
+    }
+
+    // Allocate mini-slugs and insert, retrying conflicting mini-slugs up to `allocation_retries` times
+    let mut retries_left = state.allocation_retries;
+    loop {
+        if !waiting.is_empty() {
+            if retries_left == 0 {
+                for i in waiting.drain(..) {
+                    statuses[i] = StatusCode::CONFLICT;
+                }
+            } else {
+                retries_left -= 1;
+                match state.pool.pop_many(waiting.len()).await {
+                    Ok(slugs) => {
+                        metrics::counter!("write_allocations_total").increment(slugs.len() as u64);
+                        let mut slugs = slugs.into_iter();
+                        for i in waiting.drain(..) {
+                            match slugs.next() {
+                                Some(slug) => inserting.push((i, slug, false)),
+                                // Pool empty
+                                None => statuses[i] = StatusCode::SERVICE_UNAVAILABLE,
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to pop slugs: {}", e);
+                        for i in waiting.drain(..) {
+                            statuses[i] = StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                    }
+                }
+            }
+        }
+        if inserting.is_empty() {
+            break;
+        }
+
+        let links: Vec<_> = inserting
+            .iter()
+            .map(|(i, slug, _)| new_link(slug, &payloads[*i]))
+            .collect();
+        match state.store.insert_many(&links).await {
+            Ok(inserted) => {
+                for (((i, slug, custom), link), inserted) in
+                    inserting.drain(..).zip(&links).zip(inserted)
+                {
+                    if inserted {
+                        cache_slug(&state, link);
+                        payloads[i].slug = Some(slug);
+                    } else if custom {
+                        statuses[i] = StatusCode::CONFLICT;
+                    } else {
+                        tracing::debug!("Slug {slug} already exists, retrying");
+                        metrics::counter!("write_retry_total").increment(1);
+                        waiting.push(i);
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to insert slugs: {}", e);
+                for (i, _, _) in inserting.drain(..) {
+                    statuses[i] = StatusCode::SERVICE_UNAVAILABLE;
+                }
+            }
+        }
+    }
+
+    // Return the created and deduplicated payloads
+    let results: Vec<_> = payloads
+        .into_iter()
+        .zip(statuses)
+        .map(|(payload, status)| BatchResult {
+            link: status.is_success().then_some(payload),
+            status: status.as_u16(),
+        })
+        .collect();
+    Ok(Json(results))
+}
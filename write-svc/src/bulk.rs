@@ -0,0 +1,327 @@
+use crate::{AppState, auth::Caller, cache_slug};
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use csv_async::AsyncReaderBuilder;
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use min_url_core::{
+    link::{check_custom_slug, is_http_url, resolve_expiration},
+    slug::RedirectType,
+    store::{LinkFilter, NewLink},
+};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio_util::io::StreamReader;
+use url::Url;
+
+/// Links inserted per COPY
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Imported link, as a CSV record or a JSON line, exports can be imported back
+#[derive(Deserialize)]
+struct ImportRow {
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    redirect_type: Option<RedirectType>,
+    slug: String,
+    url: String,
+}
+
+/// Parsed row, or the slug if known and the reason it was rejected
+type ParsedRow = Result<ImportRow, (Option<String>, String)>;
+
+/// Import report JSON payload, rows are numbered from 1, headers excluded
+#[derive(Serialize)]
+struct ImportReport {
+    errors: Vec<RowError>,
+    failed: usize,
+    imported: usize,
+}
+
+/// Rejected row
+#[derive(Serialize)]
+struct RowError {
+    error: String,
+    row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slug: Option<String>,
+}
+
+/// Bulk file format
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    #[default]
+    Jsonl,
+}
+
+impl Format {
+    /// Media type
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Jsonl => "application/x-ndjson",
+        }
+    }
+}
+
+/// Export query parameters, the range applies to the creation date
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    to: Option<DateTime<Utc>>,
+}
+
+/// Import links handler, streams a CSV or JSONL upload and reports rejected rows
+///
+/// Slugs are required, valid rows are inserted even if others are rejected. If the store fails, the rest of the upload is reported as not imported, with a 503.
+pub async fn handle_links_post(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, StatusCode> {
+    // If anonymous, refuse, imported links need an owner or an admin
+    if let Caller::Anonymous = caller {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Pick the parser from the content type
+    let format = match headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+    {
+        Some("text/csv") => Format::Csv,
+        Some("application/x-ndjson" | "application/jsonl") => Format::Jsonl,
+        _ => return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    };
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let mut rows = parse_rows(format, reader);
+
+    // Validate rows and insert them in batches
+    let mut report = ImportReport {
+        errors: Vec::new(),
+        failed: 0,
+        imported: 0,
+    };
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut available = true;
+    let mut row = 0;
+    while let Some(parsed) = rows.next().await {
+        row += 1;
+        let parsed = parsed.map_err(|e| {
+            tracing::debug!("Failed to read import: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        match parsed.and_then(|parsed| validate(parsed, &caller)) {
+            Ok(link) if available => batch.push((row, link)),
+            // Once the store failed, valid rows are not imported either
+            Ok(link) => report.errors.push(unavailable(row, link)),
+            Err((slug, error)) => report.errors.push(RowError { error, row, slug }),
+        }
+        if batch.len() == IMPORT_BATCH_SIZE {
+            available = import_batch(&state, &mut batch, &mut report).await;
+        }
+    }
+    if available {
+        available = import_batch(&state, &mut batch, &mut report).await;
+    }
+    report.errors.sort_by_key(|error| error.row);
+    report.failed = report.errors.len();
+    tracing::info!(
+        "Imported {} links, rejected {}",
+        report.imported,
+        report.failed
+    );
+
+    let status = if available {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(report)))
+}
+
+/// Export links handler, streams links as CSV or JSONL, oldest first
+pub async fn handle_links_get(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ExportQuery>,
+    caller: Caller,
+) -> Result<Response, StatusCode> {
+    // If anonymous, refuse, owners export their own links and admins anyone's
+    if let Caller::Anonymous = caller {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let filter = LinkFilter {
+        from: query.from,
+        owner: caller.resolve_owner(query.owner)?,
+        to: query.to,
+    };
+
+    // Stream from the store
+    let links = state.store.export(&filter).await.map_err(|e| {
+        tracing::error!("Failed to export links: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+    let chunks = match query.format {
+        Format::Csv => {
+            // Headers go with the first record only
+            let mut has_headers = true;
+            links
+                .map(move |link| {
+                    let mut writer = csv::WriterBuilder::new()
+                        .has_headers(std::mem::take(&mut has_headers))
+                        .from_writer(Vec::new());
+                    writer.serialize(link?)?;
+                    Ok(Bytes::from(writer.into_inner()?))
+                })
+                .boxed()
+        }
+        Format::Jsonl => links
+            .map(|link| {
+                let mut line = serde_json::to_vec(&link?)?;
+                line.push(b'\n');
+                Ok(Bytes::from(line))
+            })
+            .boxed(),
+    };
+    let chunks = chunks.inspect_err(|e: &anyhow::Error| {
+        tracing::error!("Failed to stream export, truncated: {}", e);
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, query.format.content_type())],
+        Body::from_stream(chunks),
+    )
+        .into_response())
+}
+
+/// Parse an upload into rows, fails on read errors, rows failing to parse are reported
+fn parse_rows<R: AsyncBufRead + Send + Unpin + 'static>(
+    format: Format,
+    reader: R,
+) -> BoxStream<'static, io::Result<ParsedRow>> {
+    match format {
+        Format::Csv => AsyncReaderBuilder::new()
+            .create_deserializer(reader)
+            .into_deserialize::<ImportRow>()
+            .map(|parsed| match parsed {
+                Ok(parsed) => Ok(Ok(parsed)),
+                Err(e) if e.is_io_error() => Err(io::Error::other(e)),
+                Err(e) => Ok(Err((None, e.to_string()))),
+            })
+            .boxed(),
+        // Blank lines are skipped
+        Format::Jsonl => futures_util::stream::unfold(reader.lines(), |mut lines| async move {
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) if line.trim().is_empty() => continue,
+                    Ok(Some(line)) => {
+                        let parsed = serde_json::from_str(&line).map_err(|e| (None, e.to_string()));
+                        return Some((Ok(parsed), lines));
+                    }
+                    Ok(None) => return None,
+                    Err(e) => return Some((Err(e), lines)),
+                }
+            }
+        })
+        .boxed(),
+    }
+}
+
+/// Check a row like the shorten handler does, returns the link to insert or the slug and the reason
+fn validate(row: ImportRow, caller: &Caller) -> Result<NewLink, (Option<String>, String)> {
+    let reject = |error: &str| Err((Some(row.slug.clone()), error.to_string()));
+
+    // Check the URL is HTTP(S)
+    let url = match Url::parse(&row.url) {
+        Ok(url) if is_http_url(&url) => url,
+        Ok(_) => return reject("URL must be HTTP(S)"),
+        Err(_) => return reject("invalid URL"),
+    };
+
+    // Check the slug fits and has a partition
+    if let Err(e) = check_custom_slug(&row.slug) {
+        return reject(&e.to_string());
+    }
+
+    // Check the expiration and the owner
+    let Ok(expires_at) = resolve_expiration(row.expires_at, None) else {
+        return reject("expires_at must be in the future");
+    };
+    let Ok(owner) = caller.resolve_owner(row.owner.clone()) else {
+        return reject("owner must be the caller");
+    };
+
+    Ok(NewLink {
+        expires_at,
+        owner,
+        redirect_type: row.redirect_type.unwrap_or_default(),
+        slug: row.slug,
+        url: url.to_string(),
+    })
+}
+
+/// Insert a batch of validated rows and cache them, taken slugs are reported, returns false if the store failed
+async fn import_batch(
+    state: &AppState,
+    batch: &mut Vec<(usize, NewLink)>,
+    report: &mut ImportReport,
+) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+    let (rows, links): (Vec<usize>, Vec<NewLink>) = batch.drain(..).unzip();
+    let inserted = match state.store.import(&links).await {
+        Ok(inserted) => inserted,
+        Err(e) => {
+            tracing::error!("Failed to import links: {}", e);
+            let failed = rows.into_iter().zip(links);
+            report
+                .errors
+                .extend(failed.map(|(row, link)| unavailable(row, link)));
+            return false;
+        }
+    };
+    for ((row, link), inserted) in rows.into_iter().zip(links).zip(inserted) {
+        if inserted {
+            cache_slug(state, &link);
+            report.imported += 1;
+        } else {
+            report.errors.push(RowError {
+                error: "slug taken".to_string(),
+                row,
+                slug: Some(link.slug),
+            });
+        }
+    }
+    true
+}
+
+/// Report of a valid row not imported as the store failed
+fn unavailable(row: usize, link: NewLink) -> RowError {
+    RowError {
+        error: "store unavailable".to_string(),
+        row,
+        slug: Some(link.slug),
+    }
+}
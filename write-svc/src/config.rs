@@ -0,0 +1,150 @@
+use anyhow::{Result, ensure};
+use clap::Parser;
+use min_url_core::{
+    config::{
+        self, ConfigArgs, Settings, default_bind_addr, default_metrics_addr,
+        default_shutdown_timeout_seconds,
+    },
+    link::DEFAULT_ALLOCATION_RETRIES,
+    net::TrustedProxies,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// write-svc settings
+#[derive(Deserialize)]
+pub struct Config {
+    /// Token granting admin rights, no caller is admin if unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Pool slugs tried before giving up on a conflict
+    #[serde(default = "default_allocation_retries")]
+    pub allocation_retries: u32,
+    /// Public listener
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: SocketAddr,
+    pub database_url: String,
+    /// Seconds responses to `Idempotency-Key` requests are kept
+    #[serde(default = "default_idempotency_ttl_seconds")]
+    pub idempotency_ttl_seconds: u64,
+    #[serde(default = "default_metrics_addr")]
+    pub metrics_addr: SocketAddr,
+    /// Requests a client can burst before being throttled
+    #[serde(default = "default_rate_limit_capacity")]
+    pub rate_limit_capacity: u32,
+    /// Requests a client regains per second
+    #[serde(default = "default_rate_limit_refill_per_second")]
+    pub rate_limit_refill_per_second: u32,
+    pub redis_url: String,
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+    /// Reverse proxies whose `X-Forwarded-For` is trusted to identify anonymous clients
+    #[serde(default)]
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl Config {
+    /// Load from the configuration file, environment variables and CLI flags
+    pub fn load() -> Result<Self> {
+        let cli = Cli::parse();
+        config::load(&cli.args, &cli)
+    }
+
+    /// Shutdown drain deadline
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.admin_token
+                .as_ref()
+                .is_none_or(|token| !token.trim().is_empty()),
+            "admin_token must not be blank"
+        );
+        ensure!(
+            self.allocation_retries > 0,
+            "allocation_retries must be positive"
+        );
+        ensure!(
+            self.idempotency_ttl_seconds > 0,
+            "idempotency_ttl_seconds must be positive"
+        );
+        ensure!(
+            self.rate_limit_capacity > 0,
+            "rate_limit_capacity must be positive"
+        );
+        ensure!(
+            self.rate_limit_refill_per_second > 0,
+            "rate_limit_refill_per_second must be positive"
+        );
+        Ok(())
+    }
+}
+
+/// Shorten URLs and manage links
+///
+/// Settings are read from the configuration file, then environment variables (e.g. `ADMIN_TOKEN`), then these flags.
+#[derive(Parser, Serialize)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    #[serde(skip)]
+    args: ConfigArgs,
+    /// Token granting admin rights
+    #[arg(long)]
+    admin_token: Option<String>,
+    /// Pool slugs tried before giving up on a conflict [default: 6]
+    #[arg(long)]
+    allocation_retries: Option<u32>,
+    /// Public listener [default: 0.0.0.0:8080]
+    #[arg(long)]
+    bind_addr: Option<SocketAddr>,
+    /// PostgreSQL URL
+    #[arg(long)]
+    database_url: Option<String>,
+    /// Seconds responses to `Idempotency-Key` requests are kept [default: 86400]
+    #[arg(long)]
+    idempotency_ttl_seconds: Option<u64>,
+    /// Prometheus metrics listener [default: 0.0.0.0:9090]
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Requests a client can burst before being throttled [default: 20]
+    #[arg(long)]
+    rate_limit_capacity: Option<u32>,
+    /// Requests a client regains per second [default: 5]
+    #[arg(long)]
+    rate_limit_refill_per_second: Option<u32>,
+    /// Redis URL
+    #[arg(long)]
+    redis_url: Option<String>,
+    /// Seconds to drain requests, then pending writes, on shutdown [default: 10]
+    #[arg(long)]
+    shutdown_timeout_seconds: Option<u64>,
+    /// Reverse proxies trusted to report client addresses in `X-Forwarded-For`, comma-separated addresses or CIDR networks [default: none]
+    #[arg(long)]
+    trusted_proxies: Option<String>,
+}
+
+/// Default pool slugs tried
+fn default_allocation_retries() -> u32 {
+    DEFAULT_ALLOCATION_RETRIES
+}
+
+/// Default idempotency key lifetime, a day
+fn default_idempotency_ttl_seconds() -> u64 {
+    86_400
+}
+
+/// Default rate limit burst
+fn default_rate_limit_capacity() -> u32 {
+    20
+}
+
+/// Default rate limit refill rate
+fn default_rate_limit_refill_per_second() -> u32 {
+    5
+}
@@ -0,0 +1,42 @@
+use crate::AppState;
+use axum::{Json, extract::State, response::IntoResponse};
+use futures_util::FutureExt;
+use min_url_core::health::Readiness;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Readiness JSON payload
+#[derive(Serialize)]
+struct ReadyPayload {
+    #[serde(flatten)]
+    readiness: Readiness,
+    /// Mini-slugs left to allocate, unknown if Redis is unreachable
+    slug_pool: Option<usize>,
+}
+
+/// Readiness handler, returns 503 if PostgreSQL or Redis is unreachable
+///
+/// An empty slug pool does not make the service unready, custom slugs can still be created.
+pub async fn handle_ready_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Redis is checked by reading the pool size
+    let mut slug_pool = None;
+    let readiness = Readiness::check([
+        ("postgres", state.store.ping().boxed()),
+        (
+            "redis",
+            async {
+                slug_pool = Some(state.pool.size().await?);
+                Ok(())
+            }
+            .boxed(),
+        ),
+    ])
+    .await;
+    (
+        readiness.code(),
+        Json(ReadyPayload {
+            readiness,
+            slug_pool,
+        }),
+    )
+}
@@ -0,0 +1,119 @@
+use crate::{AppState, auth::Caller, hash_key};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use min_url_core::store::{IdempotencyRecord, StoredResponse};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Header carrying the client-chosen idempotency key
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Longest accepted idempotency key
+const MAX_KEY_LEN: usize = 255;
+
+/// Seconds a key stays claimed by an in-flight request, for retries not to be blocked long if it never completes
+const PENDING_TTL_SECONDS: u64 = 60;
+
+/// Idempotency key claimed by a request
+pub enum Claim {
+    /// No key, or storage unavailable
+    None,
+    /// Key claimed, the response must be stored or the claim released
+    Claimed { fingerprint: String, key: String },
+    /// Key used by a completed request, with its response
+    Replay(StatusCode, Value),
+}
+
+impl Claim {
+    /// Claim the request idempotency key, scoped to the caller, or find the response to replay
+    ///
+    /// Rejects keys in use by an in-flight request (409) or by another payload (422). Fails open: if storage is unavailable, the request goes through.
+    pub async fn acquire(
+        state: &AppState,
+        caller: &Caller,
+        headers: &HeaderMap,
+        payload: &impl Serialize,
+    ) -> Result<Self, StatusCode> {
+        // If no key, nothing to claim
+        let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+            return Ok(Self::None);
+        };
+        let idempotency_key = value
+            .to_str()
+            .ok()
+            .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        // Key by caller, so callers cannot replay each other's responses
+        let scope = match caller {
+            Caller::Anonymous => "anonymous",
+            Caller::Owner(owner) => owner,
+            Caller::Admin => "admin",
+        };
+        let key = hex(&hash_key(&format!("{scope}\n{idempotency_key}")));
+        let fingerprint = hex(&hash_key(
+            &serde_json::to_string(payload).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        ));
+
+        let claimed = state
+            .idempotency
+            .claim(&key, &fingerprint, PENDING_TTL_SECONDS)
+            .await;
+        match claimed {
+            Ok(None) => Ok(Self::Claimed { fingerprint, key }),
+            Ok(Some(record)) if record.fingerprint != fingerprint => {
+                Err(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            Ok(Some(IdempotencyRecord { response: None, .. })) => Err(StatusCode::CONFLICT),
+            Ok(Some(IdempotencyRecord {
+                response: Some(response),
+                ..
+            })) => {
+                tracing::debug!("Replaying idempotent request {key}");
+                let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::CREATED);
+                Ok(Self::Replay(status, response.body))
+            }
+            Err(e) => {
+                tracing::warn!("Failed to claim idempotency key, letting request through: {e:?}");
+                Ok(Self::None)
+            }
+        }
+    }
+
+    /// Store the response for retries, until the key expires
+    pub async fn complete(self, state: &AppState, status: StatusCode, body: &impl Serialize) {
+        let Self::Claimed { fingerprint, key } = self else {
+            return;
+        };
+        let stored = async {
+            let record = IdempotencyRecord {
+                fingerprint,
+                response: Some(StoredResponse {
+                    body: serde_json::to_value(body)?,
+                    status: status.as_u16(),
+                }),
+            };
+            state
+                .idempotency
+                .complete(&key, &record, state.idempotency_ttl_seconds)
+                .await
+        };
+        if let Err(e) = stored.await {
+            tracing::warn!("Failed to store idempotent response {key}: {e:?}");
+        }
+    }
+
+    /// Release the key after a failed request, for the client to retry
+    pub async fn release(self, state: &AppState) {
+        let Self::Claimed { key, .. } = self else {
+            return;
+        };
+        if let Err(e) = state.idempotency.release(&key).await {
+            tracing::warn!("Failed to release idempotency key {key}: {e:?}");
+        }
+    }
+}
+
+/// Lowercase hex encoding
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,275 @@
+//! Write path of min-url-rs: shortens URLs and manages links, API keys and stats.
+
+mod auth;
+mod batch;
+mod bulk;
+mod health;
+mod idempotency;
+mod links;
+mod rate_limit;
+mod stats;
+
+pub use auth::hash_key;
+pub use rate_limit::RateLimit;
+
+use anyhow::Result;
+use auth::Caller;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use chrono::{DateTime, Utc};
+use idempotency::Claim;
+use min_url_core::{
+    health::handle_health_get,
+    link::{AllocationError, allocate, check_custom_slug, is_http_url, resolve_expiration},
+    net::TrustedProxies,
+    slug::RedirectType,
+    store::{
+        ClickStore, IdempotencyStore, KeyStore, NewLink, RateLimiter, SlugCache, SlugPool,
+        SlugStore,
+    },
+    telemetry::{make_request_span, track_metrics},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_util::task::TaskTracker;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::CompressionLayer, decompression::RequestDecompressionLayer, trace::TraceLayer,
+};
+use url::Url;
+
+/// Shorten URL JSON payload
+#[derive(Deserialize, Serialize)]
+struct ShortenPayload {
+    /// Return the caller's live link to the same URL, if any, instead of allocating a mini-slug
+    #[serde(default)]
+    dedupe: bool,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    redirect_type: RedirectType,
+    #[serde(default)]
+    slug: Option<String>,
+    #[serde(default)]
+    ttl_seconds: Option<u64>,
+    url: Url,
+}
+
+/// Web application state
+pub struct AppState {
+    /// Hash of the admin token, see `hash_key`
+    pub admin_token_hash: Option<Vec<u8>>,
+    pub allocation_retries: u32,
+    /// Redis cache read by redirect-svc
+    pub cache: Arc<dyn SlugCache>,
+    /// Clicks recorded by redirect-svc, for stats
+    pub clicks: Arc<dyn ClickStore>,
+    pub idempotency: Arc<dyn IdempotencyStore>,
+    /// Responses to `Idempotency-Key` requests are kept this long
+    pub idempotency_ttl_seconds: u64,
+    pub keys: Arc<dyn KeyStore>,
+    /// Pre-generated mini-slugs
+    pub pool: Arc<dyn SlugPool>,
+    pub rate_limit: RateLimit,
+    pub rate_limiter: Arc<dyn RateLimiter>,
+    pub store: Arc<dyn SlugStore>,
+    /// Fire & forget tasks, awaited on shutdown
+    pub tasks: TaskTracker,
+    /// Proxies reporting anonymous client addresses, for rate limiting
+    pub trusted_proxies: TrustedProxies,
+}
+
+/// Build the web application, with its middlewares
+pub fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/shorten", post(handle_shorten_post))
+        .route("/shorten/batch", post(batch::handle_shorten_batch_post))
+        .route(
+            "/links",
+            get(bulk::handle_links_get).post(bulk::handle_links_post),
+        )
+        .route(
+            "/links/{slug}",
+            get(links::handle_link_get)
+                .patch(links::handle_link_patch)
+                .delete(links::handle_link_delete),
+        )
+        .route("/links/{slug}/stats", get(stats::handle_stats_get))
+        .route("/admin/keys", post(auth::handle_key_post))
+        .route("/admin/keys/{id}", delete(auth::handle_key_delete))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit,
+        ))
+        // Probes, registered after the rate limiter to never be throttled
+        .route("/_/health", get(handle_health_get))
+        .route("/_/ready", get(health::handle_ready_get))
+        .with_state(state)
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+                .layer(middleware::from_fn(track_metrics))
+                .layer(RequestDecompressionLayer::new())
+                .layer(CompressionLayer::new()),
+        )
+}
+
+/// Shorten URL handler, replaying the response of a previous request with the same `Idempotency-Key`
+async fn handle_shorten_post(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    headers: HeaderMap,
+    Json(payload): Json<ShortenPayload>,
+) -> Result<Response, StatusCode> {
+    let claim = match Claim::acquire(&state, &caller, &headers, &payload).await? {
+        Claim::Replay(status, body) => return Ok((status, Json(body)).into_response()),
+        claim => claim,
+    };
+
+    // Keep the response for retries, or let them try again on failure
+    match shorten(&state, &caller, payload).await {
+        Ok((status, payload)) => {
+            claim.complete(&state, status, &payload).await;
+            Ok((status, Json(payload)).into_response())
+        }
+        Err(status) => {
+            claim.release(&state).await;
+            Err(status)
+        }
+    }
+}
+
+/// Create a link, returns 201 with the payload, or 200 with the existing link if deduplicated
+async fn shorten(
+    state: &AppState,
+    caller: &Caller,
+    mut payload: ShortenPayload,
+) -> Result<(StatusCode, ShortenPayload), StatusCode> {
+    validate_payload(&mut payload, caller)?;
+
+    // If deduplicating, return the existing link
+    if payload.dedupe && payload.slug.is_none() && dedupe_payload(state, &mut payload).await? {
+        return Ok((StatusCode::OK, payload));
+    }
+
+    // If slug is provided, insert
+    let mut link = new_link("", &payload);
+    if let Some(custom) = payload.slug.take() {
+        link.slug = custom;
+        match state.store.insert(&link).await {
+            Ok(true) => (),
+            Ok(false) => return Err(StatusCode::CONFLICT),
+            Err(e) => {
+                tracing::error!("Failed to insert slug: {}", e);
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        }
+
+    // Otherwise, allocate a mini-slug from the pool, retrying up to `allocation_retries` times
+    } else {
+        let allocated = allocate(
+            state.pool.as_ref(),
+            state.store.as_ref(),
+            &mut link,
+            state.allocation_retries,
+        )
+        .await;
+        match allocated {
+            Ok(()) => (),
+            Err(AllocationError::NoSlug) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+            Err(AllocationError::Conflict) => return Err(StatusCode::CONFLICT),
+            Err(AllocationError::Unavailable(e)) => {
+                tracing::error!("Failed to allocate slug: {}", e);
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        }
+    }
+    cache_slug(state, &link);
+
+    // Return the payload
+    payload.slug = Some(link.slug);
+    Ok((StatusCode::CREATED, payload))
+}
+
+/// Fill the payload with the owner's live link to the same URL, returns false if there is none
+///
+/// URLs are compared as normalized by parsing (lowercase scheme and host, no default port).
+async fn dedupe_payload(
+    state: &AppState,
+    payload: &mut ShortenPayload,
+) -> Result<bool, StatusCode> {
+    let link = state
+        .store
+        .find_by_url(payload.url.as_str(), payload.owner.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to find duplicate link: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?;
+    let Some(link) = link else {
+        return Ok(false);
+    };
+    tracing::debug!("Deduplicated {} to {}", payload.url, link.slug);
+    payload.expires_at = link.expires_at;
+    payload.redirect_type = link.redirect_type;
+    payload.slug = Some(link.slug);
+    Ok(true)
+}
+
+/// Check a payload and resolve its owner and expiration, in place
+fn validate_payload(payload: &mut ShortenPayload, caller: &Caller) -> Result<(), StatusCode> {
+    // Check if URL is HTTP(S)
+    if !is_http_url(&payload.url) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Check if custom slug is valid
+    if let Some(custom) = &payload.slug {
+        check_custom_slug(custom).map_err(|_| StatusCode::BAD_REQUEST)?;
+    }
+
+    // Derive the owner from the API key
+    payload.owner = caller.resolve_owner(payload.owner.take())?;
+
+    // Resolve the expiration date, either absolute or relative
+    payload.expires_at = resolve_expiration(payload.expires_at, payload.ttl_seconds.take())
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    Ok(())
+}
+
+/// Cache a created slug in Redis, expiring with the slug, and evict negative entries from memory caches (fire & forget)
+fn cache_slug(state: &AppState, link: &NewLink) {
+    let entry = link.entry();
+    let cache = state.cache.clone();
+    let slug = link.slug.clone();
+    state.tasks.spawn(async move {
+        let cached = async {
+            cache.set(&slug, &entry, None).await?;
+            cache.publish(&slug).await
+        };
+        match cached.await {
+            Ok(()) => tracing::debug!("Cached {slug} -> {} in Redis", entry.url),
+            Err(e) => tracing::warn!("Failed to cache slug {slug} in Redis: {}", e),
+        }
+    });
+}
+
+/// Link to insert for a payload
+fn new_link(slug: &str, payload: &ShortenPayload) -> NewLink {
+    NewLink {
+        expires_at: payload.expires_at,
+        owner: payload.owner.clone(),
+        redirect_type: payload.redirect_type,
+        slug: slug.to_string(),
+        url: payload.url.to_string(),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{AppState, auth::Caller};
+use anyhow::Result;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use min_url_core::{
+    link::{is_http_url, resolve_expiration},
+    slug::RedirectType,
+    store::{Link, LinkUpdate},
+};
+use serde::{Deserialize, Deserializer};
+use std::sync::Arc;
+use url::Url;
+
+/// Update link JSON payload, absent fields are left untouched
+#[derive(Deserialize)]
+pub struct UpdatePayload {
+    /// Explicit `null` clears the expiration
+    #[serde(default, deserialize_with = "deserialize_some")]
+    expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default)]
+    redirect_type: Option<RedirectType>,
+    #[serde(default)]
+    ttl_seconds: Option<u64>,
+    #[serde(default)]
+    url: Option<Url>,
+}
+
+/// Get link handler
+pub async fn handle_link_get(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, StatusCode> {
+    let link = fetch_owned_link(&state, &slug, &caller).await?;
+    Ok(Json(link))
+}
+
+/// Update link handler
+pub async fn handle_link_patch(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    caller: Caller,
+    Json(payload): Json<UpdatePayload>,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Check if URL is HTTP(S)
+    if payload.url.as_ref().is_some_and(|url| !is_http_url(url)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Resolve the expiration date, `None` leaves it untouched
+    let expires_at = match (payload.expires_at, payload.ttl_seconds) {
+        (Some(_), Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        (Some(expires_at), None) => {
+            Some(resolve_expiration(expires_at, None).map_err(|_| StatusCode::BAD_REQUEST)?)
+        }
+        (None, Some(ttl)) => {
+            Some(resolve_expiration(None, Some(ttl)).map_err(|_| StatusCode::BAD_REQUEST)?)
+        }
+        (None, None) => None,
+    };
+
+    // Check the link exists and belongs to the caller
+    fetch_owned_link(&state, &slug, &caller).await?;
+
+    // Update in PostgreSQL
+    let update = LinkUpdate {
+        expires_at,
+        redirect_type: payload.redirect_type,
+        url: payload.url.map(String::from),
+    };
+    let link = state
+        .store
+        .update(&slug, &update)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update slug: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Drop the stale cache entries
+    invalidate_cache(&state, &slug).await?;
+    tracing::debug!("Updated slug {slug}");
+
+    Ok(Json(link))
+}
+
+/// Delete link handler
+pub async fn handle_link_delete(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Check the link exists and belongs to the caller
+    fetch_owned_link(&state, &slug, &caller).await?;
+
+    // Delete from PostgreSQL
+    state.store.delete(&slug).await.map_err(|e| {
+        tracing::error!("Failed to delete slug: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    // Drop the stale cache entries
+    invalidate_cache(&state, &slug).await?;
+    tracing::debug!("Deleted slug {slug}");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fetch a link, checking the caller owns it (401 if anonymous, 404 if unknown, 403 if owned by someone else)
+pub async fn fetch_owned_link(
+    state: &AppState,
+    slug: &str,
+    caller: &Caller,
+) -> Result<Link, StatusCode> {
+    // If anonymous, skip the lookup
+    if let Caller::Anonymous = caller {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    // Look up the slug
+    let link = state
+        .store
+        .get(slug)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to lookup slug: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    // Check the owner
+    caller.check_owner(link.owner.as_deref()).inspect_err(|_| {
+        tracing::debug!("Slug {slug} not owned by the caller");
+    })?;
+
+    Ok(link)
+}
+
+/// Remove a slug from Redis and memory caches, so the next read goes to PostgreSQL
+async fn invalidate_cache(state: &AppState, slug: &str) -> Result<(), StatusCode> {
+    state.cache.invalidate(slug).await.map_err(|e| {
+        tracing::error!("Failed to invalidate slug {slug} in Redis: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+/// Deserialize a present field as `Some`, so that `null` is told apart from absent
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
@@ -1,233 +1,76 @@
+mod config;
+
 use anyhow::Result;
-use axum::{Json, Router, extract::State, http::StatusCode, response::IntoResponse, routing::post};
-use deadpool_postgres::{
-    ManagerConfig, Pool as PostgresPool, RecyclingMethod, Runtime as PgRuntime,
-    tokio_postgres::NoTls,
-};
-use deadpool_redis::{
-    Config as RedisConfig, Pool as RedisPool, Runtime as RedisRuntime, redis::cmd,
+use config::Config;
+use min_url_core::{
+    pools::Pools,
+    shutdown::{serve, shutdown_token, wait_tasks},
+    store::{
+        PgClickStore, PgKeyStore, PgSlugStore, RedisIdempotencyStore, RedisRateLimiter,
+        RedisSlugCache, RedisSlugPool,
+    },
+    telemetry::{Telemetry, init_metrics},
 };
-use serde::{Deserialize, Serialize};
-use std::env;
 use std::sync::Arc;
-use tower::ServiceBuilder;
-use tower_http::{compression::CompressionLayer, decompression::RequestDecompressionLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use url::Url;
-
-/// Shorten URL JSON payload
-#[derive(Deserialize, Serialize)]
-struct ShortenPayload {
-    #[serde(default)]
-    owner: Option<String>,
-    #[serde(default)]
-    slug: Option<String>,
-    url: Url,
-}
-
-/// Slug allocation error
-struct MiniErr {
-    status: Status,
-}
-
-/// Slug allocation error status
-enum Status {
-    NoSlug,
-    DbConflict,
-    Other,
-}
+use tokio_util::task::TaskTracker;
+use write_svc::{AppState, RateLimit, app, hash_key};
 
-/// Web application state
-struct AppState {
-    pg_pool: PostgresPool,
-    redis_pool: RedisPool,
-}
+/// Latency histogram buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
 
 /// Entrypoint
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // Axum logs rejections from built-in extractors with the `axum::rejection` target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // Load configuration
+    let config = Config::load()?;
 
-    // Load environment variables
-    let db_url = env::var("DATABASE_URL")?;
-    let redis_url = env::var("REDIS_URL")?;
+    // Initialize telemetry
+    let telemetry = Telemetry::init(env!("CARGO_PKG_NAME"))?;
+    init_metrics(config.metrics_addr, Some(LATENCY_BUCKETS))?;
 
-    // Connect Redis
-    let redis_cfg = RedisConfig::from_url(&redis_url);
-    let redis_pool: RedisPool = redis_cfg.create_pool(Some(RedisRuntime::Tokio1))?;
+    // Shut down on SIGTERM or Ctrl+C
+    let shutdown = shutdown_token()?;
 
-    // Connect PostgreSQL
-    let mut pg_cfg = deadpool_postgres::Config::new();
-    pg_cfg.manager = Some(ManagerConfig {
-        recycling_method: RecyclingMethod::Fast,
-    });
-    pg_cfg.url = Some(db_url.clone());
-    let pg_pool: PostgresPool = pg_cfg.create_pool(Some(PgRuntime::Tokio1), NoTls)?;
+    // Connect Redis and PostgreSQL
+    let Pools {
+        pg_pool,
+        redis_pool,
+    } = Pools::connect(&config.database_url, &config.redis_url)?;
 
     // Build the app state
+    let tasks = TaskTracker::new();
     let state = Arc::new(AppState {
-        redis_pool,
-        pg_pool,
+        admin_token_hash: config.admin_token.as_deref().map(hash_key),
+        allocation_retries: config.allocation_retries,
+        cache: Arc::new(RedisSlugCache::new(redis_pool.clone(), &config.redis_url)?),
+        clicks: Arc::new(PgClickStore::new(pg_pool.clone())),
+        idempotency: Arc::new(RedisIdempotencyStore::new(redis_pool.clone())),
+        idempotency_ttl_seconds: config.idempotency_ttl_seconds,
+        keys: Arc::new(PgKeyStore::new(pg_pool.clone())),
+        pool: Arc::new(RedisSlugPool::new(redis_pool.clone())),
+        rate_limit: RateLimit {
+            capacity: config.rate_limit_capacity,
+            refill_per_second: config.rate_limit_refill_per_second,
+        },
+        rate_limiter: Arc::new(RedisRateLimiter::new(redis_pool)),
+        store: Arc::new(PgSlugStore::new(pg_pool)),
+        tasks: tasks.clone(),
+        trusted_proxies: config.trusted_proxies.clone(),
     });
 
-    // Register the shorten handler
-    let app = Router::new()
-        .route("/shorten", post(handle_shorten_post))
-        .with_state(state)
-        .layer(
-            ServiceBuilder::new()
-                .layer(RequestDecompressionLayer::new())
-                .layer(CompressionLayer::new()),
-        );
+    // Register the handlers
+    let app = app(state);
 
-    // Start the server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+    // Start the server, until shutdown
+    let listener = tokio::net::TcpListener::bind(config.bind_addr).await?;
     tracing::info!("write-svc running on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    serve(listener, app, &shutdown, config.shutdown_timeout()).await?;
 
-    Ok(())
-}
-
-/// Shorten URL handler
-async fn handle_shorten_post(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<ShortenPayload>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Check if URL is HTTP(S)
-    if payload.url.scheme() != "http" && payload.url.scheme() != "https" {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    // If slug is provided, insert
-    let slug = if let Some(custom) = payload.slug {
-        // Check if slug has a valid length
-        if custom.len() < 3 || custom.len() > 256 {
-            return Err(StatusCode::BAD_REQUEST);
-        }
-
-        match insert_slug(&state, &custom, &payload.url, &payload.owner).await {
-            Ok(true) => custom,
-            Ok(false) => return Err(StatusCode::CONFLICT),
-            Err(e) => {
-                tracing::error!("Failed to insert slug: {}", e);
-                return Err(StatusCode::SERVICE_UNAVAILABLE);
-            }
-        }
-
-    // Otherwise, allocate a mini-slug from the pool
-    } else {
-        allocate_mini_slug(&state, &payload)
-            .await
-            .map_err(|e| match e.status {
-                Status::NoSlug => StatusCode::SERVICE_UNAVAILABLE,
-                Status::DbConflict => StatusCode::CONFLICT,
-                Status::Other => StatusCode::SERVICE_UNAVAILABLE,
-            })?
-    };
-
-    // Try to get a Redis connection
-    let mut redis_conn = state.redis_pool.get().await.map_err(|e| {
-        tracing::error!("Failed to get Redis connection: {}", e);
-        StatusCode::SERVICE_UNAVAILABLE
-    })?;
-
-    // Cache in Redis (fire & forget)
-    let url_clone = payload.url.clone();
-    let slug_clone = slug.clone();
-    tokio::spawn(async move {
-        cmd("SET")
-            .arg(&slug_clone)
-            .arg(url_clone.as_str())
-            .query_async::<()>(&mut redis_conn)
-            .await
-            .unwrap();
-        tracing::debug!("Cached {slug_clone} -> {url_clone} in Redis");
-    });
-
-    // Return the payload
-    Ok((
-        StatusCode::CREATED,
-        Json(ShortenPayload {
-            owner: payload.owner,
-            slug: Some(slug),
-            url: payload.url,
-        }),
-    ))
-}
-
-/// Allocate a mini-slug from the pool, retrying up to 6 times
-async fn allocate_mini_slug(state: &AppState, payload: &ShortenPayload) -> Result<String, MiniErr> {
-    // Retry to consume the queue up to 6 times
-    for retry in 0..6 {
-        // 1, pop slug from Redis list
-        let mut rconn = state.redis_pool.get().await.map_err(|_| MiniErr {
-            status: Status::Other,
-        })?;
-        let slug_opt: Option<String> = cmd("RPOP")
-            .arg("slug_pool")
-            .query_async(&mut rconn)
-            .await
-            .map_err(|_| MiniErr {
-            status: Status::Other,
-        })?;
-        let slug = match slug_opt {
-            // If we got a slug, return it
-            Some(s) => s,
-            // If we didn't, return an error
-            None => {
-                return Err(MiniErr {
-                    status: Status::NoSlug,
-                });
-            }
-        };
-
-        // 2, try insert into Postgres
-        match insert_slug(state, &slug, &payload.url, &payload.owner).await {
-            // If inserted, return the slug
-            Ok(true) => return Ok(slug),
-            // If conflict, retry
-            Ok(false) => {
-                tracing::debug!("Slug {slug} already exists, retrying ({retry})");
-                continue;
-            }
-            // If error, return error
-            Err(_) => {
-                return Err(MiniErr {
-                    status: Status::Other,
-                });
-            }
-        }
-    }
-
-    // 3, exhausted all retries
-    Err(MiniErr {
-        status: Status::DbConflict,
-    })
-}
+    // Wait for pending Redis writes
+    wait_tasks(&tasks, config.shutdown_timeout()).await;
+    tracing::info!("write-svc stopped");
 
-/// DB insert helper (returns Ok(true) if inserted, Ok(false) on conflict)
-async fn insert_slug(
-    state: &AppState,
-    slug: &str,
-    url: &Url,
-    owner: &Option<String>,
-) -> Result<bool> {
-    let client = state.pg_pool.get().await?;
-    let rows = client
-        .execute("INSERT INTO slugs (first_char, slug, url, owner) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING", &[&(&slug[0..1]), &slug, &url.as_str(), &owner])
-        .await?;
-    Ok(rows == 1)
+    telemetry.shutdown()
 }
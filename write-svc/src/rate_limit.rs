@@ -0,0 +1,118 @@
+use crate::{
+    AppState,
+    auth::{Caller, hash_key},
+};
+use axum::{
+    Extension,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Token bucket settings, shared by all clients
+pub struct RateLimit {
+    /// Requests a client can burst before being throttled
+    pub capacity: u32,
+    /// Requests a client regains per second
+    pub refill_per_second: u32,
+}
+
+/// Bucket of the client, in request extensions unless the client is not limited
+#[derive(Clone)]
+pub struct RateLimitKey(String);
+
+/// Rate limiting middleware, keyed by API key or client IP, returns 429 with `Retry-After` when throttled
+///
+/// Authenticates the caller for handlers, and rejects invalid credentials once charged. Every request costs a token, handlers doing more work charge the rest with [`charge`].
+pub async fn limit(
+    State(state): State<Arc<AppState>>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // Identify the client, by API key once verified, otherwise by client IP
+    let caller = Caller::authenticate(req.headers(), &state).await;
+    let key = match &caller {
+        Ok(Caller::Admin) => None,
+        Ok(Caller::Owner(_)) => key_bucket(req.headers()),
+        Ok(Caller::Anonymous) | Err(_) => ip_bucket(&state, req.headers(), peer),
+    };
+
+    // Take a token from the bucket, even if the credentials are invalid
+    if let Some(key) = key {
+        if let Err(throttled) = charge(&state, &key, 1).await {
+            return throttled;
+        }
+        req.extensions_mut().insert(key);
+    }
+    match caller {
+        Ok(caller) => {
+            req.extensions_mut().insert(caller);
+            next.run(req).await
+        }
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Take tokens from the client bucket, returns 429 with `Retry-After` when it holds fewer
+///
+/// Fails open: if the limiter is unavailable, requests go through.
+pub async fn charge(state: &AppState, key: &RateLimitKey, tokens: u32) -> Result<(), Response> {
+    let taken = state
+        .rate_limiter
+        .take(
+            &key.0,
+            tokens,
+            state.rate_limit.capacity,
+            state.rate_limit.refill_per_second,
+        )
+        .await;
+    let retry_ms = match taken {
+        Ok(None) => return Ok(()),
+        Ok(Some(retry_ms)) => retry_ms,
+        Err(e) => {
+            tracing::warn!("Failed to rate limit, letting request through: {e:?}");
+            return Ok(());
+        }
+    };
+
+    // If throttled, tell the client when to retry
+    tracing::debug!("Rate limited {}, retry in {retry_ms}ms", key.0);
+    Err((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(
+            header::RETRY_AFTER,
+            retry_ms.div_ceil(1000).max(1).to_string(),
+        )],
+    )
+        .into_response())
+}
+
+/// Bucket of an authenticated API key, keyed by its hash
+fn key_bucket(headers: &HeaderMap) -> Option<RateLimitKey> {
+    let token = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    let hex: String = hash_key(token)[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Some(RateLimitKey(format!("rate_limit:key:{hex}")))
+}
+
+/// Bucket of the client IP, as reported by trusted proxies
+fn ip_bucket(
+    state: &AppState,
+    headers: &HeaderMap,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Option<RateLimitKey> {
+    let ip = state
+        .trusted_proxies
+        .client_ip(peer.map(|Extension(ConnectInfo(addr))| addr), headers)?;
+    Some(RateLimitKey(format!("rate_limit:ip:{ip}")))
+}
@@ -0,0 +1,42 @@
+use crate::{AppState, auth::Caller, links::fetch_owned_link};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use min_url_core::store::{Bucket, ClickStats, StatsQuery};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Link stats JSON payload
+#[derive(Serialize)]
+struct StatsPayload {
+    bucket: Bucket,
+    slug: String,
+    #[serde(flatten)]
+    stats: ClickStats,
+}
+
+/// Link stats handler
+pub async fn handle_stats_get(
+    State(state): State<Arc<AppState>>,
+    Path(slug): Path<String>,
+    Query(query): Query<StatsQuery>,
+    caller: Caller,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Check the link exists and belongs to the caller
+    fetch_owned_link(&state, &slug, &caller).await?;
+
+    // Compute the stats
+    let stats = state.clicks.stats(&slug, &query).await.map_err(|e| {
+        tracing::error!("Failed to query stats: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    Ok(Json(StatsPayload {
+        bucket: query.bucket,
+        slug,
+        stats,
+    }))
+}